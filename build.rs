@@ -1,9 +1,18 @@
-use os_info;
-use std::env::{current_dir, set_current_dir};
+// Verilator and Miniconda are only downloaded and built when the
+// bootstrap-verilator and bootstrap-python features are enabled
+
+#[cfg(feature = "bootstrap-verilator")]
+use std::env::{self, set_current_dir};
+#[cfg(any(feature = "bootstrap-verilator", feature = "bootstrap-python"))]
+use std::env::current_dir;
+#[cfg(any(feature = "bootstrap-verilator", feature = "bootstrap-python"))]
 use std::io::{self, Write};
+#[cfg(any(feature = "bootstrap-verilator", feature = "bootstrap-python"))]
 use std::path::{Path, PathBuf};
+#[cfg(any(feature = "bootstrap-verilator", feature = "bootstrap-python"))]
 use std::process::Command;
 
+#[cfg(any(feature = "bootstrap-verilator", feature = "bootstrap-python"))]
 fn run_cmd(cmd: &mut Command) {
     println!("running {:?}", cmd);
     let output = cmd.output().expect("failed to execute process");
@@ -13,6 +22,7 @@ fn run_cmd(cmd: &mut Command) {
     assert!(output.status.success());
 }
 
+#[cfg(feature = "bootstrap-verilator")]
 mod verilator {
 
     use super::*;
@@ -71,9 +81,9 @@ mod verilator {
             cmd.arg("-C")
                 .arg(self.get_verilator_dir())
                 .arg("checkout")
-                .arg(&format!("v{}", self.get_version()))
+                .arg(format!("v{}", self.get_version()))
                 .arg("-b")
-                .arg(&format!("build_v{}", self.get_version()));
+                .arg(format!("build_v{}", self.get_version()));
             run_cmd(&mut cmd);
         }
 
@@ -117,7 +127,7 @@ mod verilator {
             self.cd_to_verilator_dir();
             let mut cmd = Command::new("make");
             if self.get_jobs() > 0 {
-                cmd.arg("-j").arg(&format!("{}", self.get_jobs()));
+                cmd.arg("-j").arg(format!("{}", self.get_jobs()));
             }
             run_cmd(&mut cmd);
             self.cd_to_root_dir();
@@ -178,6 +188,7 @@ mod verilator {
     }
 }
 
+#[cfg(feature = "bootstrap-python")]
 mod miniconda {
 
     use super::*;
@@ -239,33 +250,33 @@ mod miniconda {
             let script = format!("Miniconda{}-{}.sh", self.get_miniconda_version(), platform);
             let url = format!("https://repo.continuum.io/miniconda/{}", script);
             let mut cmd = Command::new("wget");
-            cmd.arg(url).arg("-O").arg(&dir.join("miniconda.sh"));
+            cmd.arg(url).arg("-O").arg(dir.join("miniconda.sh"));
             run_cmd(&mut cmd);
         }
 
         fn chmod_miniconda_sh(&self) {
             let dir = self.get_miniconda_dir();
             let mut cmd = Command::new("chmod");
-            cmd.arg("+x").arg(&dir.join("miniconda.sh"));
+            cmd.arg("+x").arg(dir.join("miniconda.sh"));
             run_cmd(&mut cmd);
         }
 
         fn run_miniconda_sh(&self) {
             let dir = self.get_miniconda_dir();
-            let mut cmd = Command::new(&dir.join("miniconda.sh"));
+            let mut cmd = Command::new(dir.join("miniconda.sh"));
             cmd.arg("-b")
                 .arg("-p")
-                .arg(&dir.join(self.get_miniconda_name()));
+                .arg(dir.join(self.get_miniconda_name()));
             run_cmd(&mut cmd);
         }
 
         fn install_pytorch(&self) {
             let m_dir = self.get_miniconda_dir();
             let i_dir = m_dir.join(self.get_miniconda_name());
-            let mut cmd = Command::new(&i_dir.join("bin/conda"));
+            let mut cmd = Command::new(i_dir.join("bin/conda"));
             cmd.arg("install")
                 .arg("-y")
-                .arg(&format!("pytorch=={}", self.get_pytorch_version()))
+                .arg(format!("pytorch=={}", self.get_pytorch_version()))
                 .arg("-c")
                 .arg("pytorch");
             run_cmd(&mut cmd);
@@ -319,23 +330,46 @@ mod miniconda {
     }
 }
 
-fn main() {
-    let root_dir = current_dir().unwrap();
+#[cfg(feature = "bootstrap-verilator")]
+fn bootstrap_verilator(root_dir: &Path) {
     let verilator_dir = root_dir.join("verilator");
     let verilator_build_dir = verilator_dir.join("build");
+    let jobs = env::var("NUM_JOBS")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(1);
+    println!("cargo:rerun-if-env-changed=VERILATOR");
+    println!("cargo:rerun-if-env-changed=VERILATOR_ROOT");
+    // a system Verilator is located by lastlayer::Build at compile time
+    if env::var_os("VERILATOR").is_none() && env::var_os("VERILATOR_ROOT").is_none() {
+        verilator::Build::new()
+            .version("4.024")
+            .jobs(jobs)
+            .root_dir(root_dir)
+            .verilator_dir(&verilator_dir)
+            .build_dir(&verilator_build_dir)
+            .compile();
+    }
+}
+
+#[cfg(feature = "bootstrap-python")]
+fn bootstrap_python(root_dir: &Path) {
     let miniconda_dir = root_dir.join("miniconda");
-    verilator::Build::new()
-        .version("4.024")
-        .jobs(1)
-        .root_dir(&root_dir)
-        .verilator_dir(&verilator_dir)
-        .build_dir(&verilator_build_dir)
-        .compile();
     miniconda::Build::new()
-        .root_dir(&root_dir)
+        .root_dir(root_dir)
         .miniconda_version("3-4.7.12.1")
         .miniconda_dir(&miniconda_dir)
         .miniconda_name("local")
         .pytorch_version("1.3.1")
         .install();
 }
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(any(feature = "bootstrap-verilator", feature = "bootstrap-python"))]
+    let root_dir = current_dir().unwrap();
+    #[cfg(feature = "bootstrap-verilator")]
+    bootstrap_verilator(&root_dir);
+    #[cfg(feature = "bootstrap-python")]
+    bootstrap_python(&root_dir);
+}
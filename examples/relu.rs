@@ -10,7 +10,7 @@ fn compile_chisel(num_vec_words: u32) {
         "runMain relu.Relu --target-dir ../relu_{} --num-vec-words {}",
         num_vec_words, num_vec_words
     );
-    change_dir(&chisel_dir);
+    change_dir(chisel_dir);
     let mut cmd = Command::new("sbt");
     cmd.arg(&sbt_opt);
     run_cmd(&mut cmd);
@@ -38,8 +38,8 @@ fn lastlayer_build(torch_dir: &Path, relu_dir: &Path, num_vec_words: u32) {
         .add_register(3, "Relu.finish", 1)
         .add_register(4, "Relu.length", 16)
         .add_register(5, "Relu.cycle", 32)
-        .add_memory(0, "Relu.rmem", mem_width.clone())
-        .add_memory(1, "Relu.wmem", mem_width.clone())
+        .add_memory(0, "Relu.rmem", mem_width)
+        .add_memory(1, "Relu.wmem", mem_width)
         .compile(&format!("relu_{}", num_vec_words));
 }
 
@@ -47,7 +47,7 @@ fn run_test(bin: &Path, relu_dir: &Path, num_vec_words: u32) {
     let mut cmd = Command::new(bin);
     cmd.arg(relu_dir.join("relu.py"))
         .arg("--num-vec-words")
-        .arg(&format!("{}", num_vec_words));
+        .arg(format!("{}", num_vec_words));
     run_cmd(&mut cmd);
 }
 
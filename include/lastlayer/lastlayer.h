@@ -5,9 +5,25 @@
 extern "C" {
 #endif
 
+/* run status, the cause is reported by LastLayerLastError. Verilator
+ * releases without vl_stop_maybe report $error and failed assertions as
+ * LASTLAYER_STOPPED. An error raised by an accessor, e.g. for a wrong hid,
+ * is returned by the next reset or run, which do not evaluate the model then.
+ * After a fatal error the device can only be deallocated and reset and run
+ * return LASTLAYER_FATAL */
+#define LASTLAYER_COMPLETED 0
+#define LASTLAYER_FINISHED 1
+#define LASTLAYER_STOPPED 2
+#define LASTLAYER_ASSERTION_FAILED 3
+#define LASTLAYER_FATAL 4
+
 /* device handle */
 typedef void* LastLayerHandle;
 
+/* cycle callback, return non-zero to stop the current run */
+typedef int (*LastLayerCycleCallback)(LastLayerHandle handle,
+    unsigned long long cycle, void* user_data);
+
 /* allocate device */
 LastLayerHandle LastLayerAlloc();
 
@@ -29,11 +45,87 @@ int LastLayerReadMem(LastLayerHandle handle,
 void LastLayerWriteMem(LastLayerHandle handle,
     int hid, int addr, int sel, int value);
 
-/* reset for n clock cycles */
-void LastLayerReset(LastLayerHandle handle, int n);
+/* read a probe, sampled after the last clock cycle evaluated */
+int LastLayerReadProbe(LastLayerHandle handle, int hid, int sel);
+
+/* force a net or variable with a Verilog force until released, after which
+ * a net follows its driver again and a variable keeps the value until it is
+ * assigned. Needs Verilator 4.220 or newer */
+void LastLayerForce(LastLayerHandle handle,
+    int hid, int sel, int value);
+
+/* release a forced signal */
+void LastLayerRelease(LastLayerHandle handle, int hid);
+
+/* read a register into words 32-bit values, LSB word first, returns 0 on
+ * success and -1 if the design is not built with SystemVerilog DPI or hid
+ * is not declared, the same applies to every Wide function */
+int LastLayerReadRegWide(LastLayerHandle handle,
+    int hid, unsigned int* value, int words);
+
+/* write a register from words 32-bit values, LSB word first */
+int LastLayerWriteRegWide(LastLayerHandle handle,
+    int hid, const unsigned int* value, int words);
+
+/* read a memory into words 32-bit values, LSB word first */
+int LastLayerReadMemWide(LastLayerHandle handle,
+    int hid, int addr, unsigned int* value, int words);
+
+/* write a memory from words 32-bit values, LSB word first */
+int LastLayerWriteMemWide(LastLayerHandle handle,
+    int hid, int addr, const unsigned int* value, int words);
+
+/* force a signal from words 32-bit values, LSB word first */
+int LastLayerForceWide(LastLayerHandle handle,
+    int hid, const unsigned int* value, int words);
+
+/* read a probe into words 32-bit values, LSB word first */
+int LastLayerReadProbeWide(LastLayerHandle handle,
+    int hid, unsigned int* value, int words);
+
+/* call fn on every rising clock edge while running, NULL to clear */
+void LastLayerSetCycleCallback(LastLayerHandle handle,
+    LastLayerCycleCallback fn, void* user_data);
+
+/* clock cycles elapsed since alloc, including reset */
+unsigned long long LastLayerGetCycle(LastLayerHandle handle);
+
+/* simulation time elapsed since alloc, ten time units per cycle */
+unsigned long long LastLayerGetTime(LastLayerHandle handle);
+
+/* save device state and counters, returns 0 on success and -1 if the
+ * file cannot be opened or written or the design is not built savable */
+int LastLayerSave(LastLayerHandle handle, const char* path);
+
+/* restore device state and counters saved with LastLayerSave, returns -1
+ * as well for a wrong or corrupt checkpoint, after which reset and run
+ * return LASTLAYER_FATAL */
+int LastLayerRestore(LastLayerHandle handle, const char* path);
+
+/* message of the last $finish, $stop, assertion or fatal error */
+const char* LastLayerLastError(LastLayerHandle handle);
+
+/* open a waveform file and start tracing, returns 0 on success
+ * and -1 if the file cannot be opened or tracing is not built in */
+int LastLayerTraceOpen(LastLayerHandle handle, const char* path);
+
+/* close the waveform file */
+void LastLayerTraceClose(LastLayerHandle handle);
+
+/* resume tracing into the open waveform file */
+void LastLayerTraceOn(LastLayerHandle handle);
+
+/* pause tracing, the waveform file stays open */
+void LastLayerTraceOff(LastLayerHandle handle);
+
+/* hierarchy levels traced by the next LastLayerTraceOpen, default 99 */
+void LastLayerTraceDepth(LastLayerHandle handle, int levels);
+
+/* reset for n clock cycles, returns run status or -1 if n is negative */
+int LastLayerReset(LastLayerHandle handle, int n);
 
-/* run for n clock cycles */
-void LastLayerRun(LastLayerHandle handle, int n);
+/* run for n clock cycles, returns run status or -1 if n is negative */
+int LastLayerRun(LastLayerHandle handle, int n);
 
 #ifdef __cplusplus
 }
@@ -1,17 +1,49 @@
-use crate::{Memory, Register};
+use crate::{Force, Memory, Probe, Register};
 use pretty::RcDoc;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
 
+// Case dispatches on the full hid, Dense requires the hids of each kind to
+// be 0..n-1 and dispatches on the low bits only, which Verilator decodes
+// without comparing every hid
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dispatch {
+    Case,
+    Dense,
+}
+
+// Verilog declares function arguments after the header, SystemVerilog uses
+// ANSI prototypes with unsigned arguments, bit variables, unique case and
+// adds wide access functions, which pass values as svBitVecVal arrays
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Language {
+    Verilog,
+    SystemVerilog,
+}
+
 #[derive(Clone, Debug)]
 enum LastLayer {
-    AWIG(String, String, String, Vec<Register>, Vec<Memory>),
+    Awig(
+        Language,
+        Dispatch,
+        String,
+        String,
+        String,
+        String,
+        String,
+        Vec<Register>,
+        Vec<Memory>,
+        Vec<Force>,
+        Vec<Probe>,
+    ),
 }
 
 trait ToDoc {
-    fn to_doc(&self) -> RcDoc<()>;
+    fn to_doc(&self) -> RcDoc<'_, ()>;
 
     fn to_pretty_with_width(&self, width: usize) -> String {
         let mut w = Vec::new();
@@ -25,21 +57,11 @@ trait ToDoc {
 }
 
 fn round_width(width: u32) -> u32 {
-    let base = 32;
-    if width % base == 0 {
-        width
-    } else {
-        ((width / base) + 1) * base
-    }
+    max_sel(width) * 32
 }
 
 fn max_sel(width: u32) -> u32 {
-    let base = 32;
-    if width % base == 0 {
-        width / base
-    } else {
-        (width / base) + 1
-    }
+    width.div_ceil(32)
 }
 
 fn func_write_name(path: &str) -> String {
@@ -50,12 +72,16 @@ fn func_read_name(path: &str) -> String {
     format!("{}_read", path.replace(".", "_").replace("[", "_").replace("]", "_"))
 }
 
-fn func_expr_eq<'a>(var: &str, val: u32) -> RcDoc<'a> {
-    RcDoc::as_string(var)
-        .append(RcDoc::space())
-        .append(RcDoc::text("=="))
-        .append(RcDoc::space())
-        .append(RcDoc::as_string(val))
+fn func_force_name(path: &str) -> String {
+    format!("{}_force", path.replace(".", "_").replace("[", "_").replace("]", "_"))
+}
+
+fn func_release_name(path: &str) -> String {
+    format!("{}_release", path.replace(".", "_").replace("[", "_").replace("]", "_"))
+}
+
+fn force_value_name(path: &str) -> String {
+    format!("{}_value", func_force_name(path))
 }
 
 fn func_expr_lt<'a>(var: &str, val: u32) -> RcDoc<'a> {
@@ -97,26 +123,100 @@ fn func_module<'a>(name: &str, body: RcDoc<'a>) -> RcDoc<'a> {
         .append(RcDoc::text("endmodule"))
 }
 
-fn func_ifeq<'a>(name: &str, hid: u32, body: RcDoc<'a>) -> RcDoc<'a> {
-    RcDoc::text("if")
+fn func_case<'a>(lang: Language, expr: RcDoc<'a>, items: Vec<RcDoc<'a>>) -> RcDoc<'a> {
+    let keyword = match lang {
+        Language::Verilog => "case",
+        Language::SystemVerilog => "unique case",
+    };
+    RcDoc::text(keyword)
         .append(RcDoc::space())
-        .append(func_paren(func_expr_eq(name, hid)))
+        .append(func_paren(expr))
         .append(RcDoc::hardline())
-        .append(func_begin_end(body))
+        .append(RcDoc::intersperse(items, RcDoc::hardline()))
+        .nest(2)
+        .group()
         .append(RcDoc::hardline())
+        .append(RcDoc::text("endcase"))
 }
 
-fn func_else<'a>(body: RcDoc<'a>) -> RcDoc<'a> {
-    RcDoc::text("else").append(RcDoc::hardline()).append(body)
+fn func_case_item<'a>(label: RcDoc<'a>, stmt: RcDoc<'a>) -> RcDoc<'a> {
+    label
+        .append(RcDoc::text(":"))
+        .append(RcDoc::space())
+        .append(stmt)
 }
 
-fn func_arg(name: &str) -> RcDoc<()> {
+// bits needed to index n dense hids
+fn hid_width(n: usize) -> u32 {
+    let mut width = 1;
+    while (1usize << width) < n {
+        width += 1;
+    }
+    width
+}
+
+// one case item per hid and a default error, an empty list only reports
+// the error since a case without items is not valid Verilog
+fn func_dispatch<'a>(
+    lang: Language,
+    dispatch: Dispatch,
+    hid: &str,
+    items: Vec<(u32, RcDoc<'a>)>,
+    empty_msg: &str,
+    wrong_msg: &str,
+) -> RcDoc<'a> {
+    if items.is_empty() {
+        return func_error(empty_msg);
+    }
+    let width = hid_width(items.len());
+    let mut stmts = Vec::new();
+    let expr = match dispatch {
+        Dispatch::Case => RcDoc::as_string(hid),
+        Dispatch::Dense => {
+            stmts.push(func_assert_lt(hid, items.len() as u32));
+            stmts.push(RcDoc::hardline());
+            RcDoc::as_string(hid).append(func_var_type(width))
+        }
+    };
+    let mut cases = Vec::new();
+    for (id, stmt) in items.into_iter() {
+        let label = match dispatch {
+            Dispatch::Case => RcDoc::as_string(id),
+            Dispatch::Dense => RcDoc::as_string(format!("{}'d{}", width, id)),
+        };
+        cases.push(func_case_item(label, stmt));
+    }
+    cases.push(func_case_item(
+        RcDoc::text("default"),
+        func_error(wrong_msg),
+    ));
+    stmts.push(func_case(lang, expr, cases));
+    RcDoc::concat(stmts)
+}
+
+fn func_type(lang: Language) -> &'static str {
+    match lang {
+        Language::Verilog => "int",
+        Language::SystemVerilog => "int unsigned",
+    }
+}
+
+fn func_arg<'a>(lang: Language, name: &str) -> RcDoc<'a> {
     RcDoc::text("input")
         .append(RcDoc::space())
-        .append(RcDoc::text("int"))
+        .append(RcDoc::text(func_type(lang)))
+        .append(RcDoc::space())
+        .append(RcDoc::as_string(name))
+}
+
+fn func_wide_arg<'a>(dir: &'a str, name: &str, width: u32) -> RcDoc<'a> {
+    RcDoc::text(dir)
+        .append(RcDoc::space())
+        .append(RcDoc::text("bit"))
+        .append(RcDoc::space())
+        .append(func_var_type(width))
         .append(RcDoc::space())
         .append(RcDoc::as_string(name))
-        .append(RcDoc::text(";"))
 }
 
 fn func_var_type<'a>(width: u32) -> RcDoc<'a> {
@@ -128,8 +228,12 @@ fn func_var_type<'a>(width: u32) -> RcDoc<'a> {
     func_bracket(ty)
 }
 
-fn func_var(name: &str, width: u32) -> RcDoc<()> {
-    RcDoc::text("reg")
+fn func_var<'a>(lang: Language, name: &str, width: u32) -> RcDoc<'a> {
+    let keyword = match lang {
+        Language::Verilog => "reg",
+        Language::SystemVerilog => "bit",
+    };
+    RcDoc::text(keyword)
         .append(RcDoc::space())
         .append(func_var_type(width))
         .append(RcDoc::space())
@@ -145,8 +249,8 @@ fn func_index_var<'a>(name: &str) -> RcDoc<'a> {
     let base = 32;
     let lhs = RcDoc::as_string(name)
         .append(RcDoc::text("*"))
-        .append(RcDoc::as_string(base.clone()));
-    let rhs = RcDoc::as_string(base.clone());
+        .append(RcDoc::as_string(base));
+    let rhs = RcDoc::as_string(base);
     func_bracket(RcDoc::concat(vec![lhs, RcDoc::text("+:"), rhs]))
 }
 
@@ -159,6 +263,20 @@ fn func_assign<'a>(lhs: RcDoc<'a>, rhs: RcDoc<'a>) -> RcDoc<'a> {
     RcDoc::concat(vec![lhs, fmt])
 }
 
+// procedural force and release, Verilator implements them from 4.220 on
+fn func_force_assign<'a>(lhs: RcDoc<'a>, rhs: RcDoc<'a>) -> RcDoc<'a> {
+    RcDoc::text("force")
+        .append(RcDoc::space())
+        .append(func_assign(lhs, rhs))
+}
+
+fn func_release_assign<'a>(lhs: RcDoc<'a>) -> RcDoc<'a> {
+    RcDoc::text("release")
+        .append(RcDoc::space())
+        .append(lhs)
+        .append(RcDoc::text(";"))
+}
+
 fn func_return<'a>(value: RcDoc<'a>) -> RcDoc<'a> {
     RcDoc::text("return")
         .append(RcDoc::space())
@@ -196,350 +314,642 @@ fn func_assert_lt<'a>(var: &str, val: u32) -> RcDoc<'a> {
     func_assert(func_expr_lt(var, val), &format!("{} out of bounds", var))
 }
 
-fn func_body<'a>(name: &str, rtype: &str, body: RcDoc<'a>) -> RcDoc<'a> {
-    RcDoc::text("function")
-        .append(RcDoc::space())
-        .append(RcDoc::as_string(rtype))
-        .append(RcDoc::space())
-        .append(RcDoc::as_string(name))
+fn func_decl<'a>(
+    lang: Language,
+    mut header: RcDoc<'a>,
+    end: &'a str,
+    args: Vec<RcDoc<'a>>,
+    body: RcDoc<'a>,
+) -> RcDoc<'a> {
+    let mut lines = Vec::new();
+    match lang {
+        Language::Verilog => lines.extend(args.into_iter().map(|a| a.append(RcDoc::text(";")))),
+        Language::SystemVerilog => {
+            header = header.append(func_paren(RcDoc::intersperse(args, RcDoc::text(", "))))
+        }
+    }
+    lines.push(body);
+    header
         .append(RcDoc::text(";"))
         .append(RcDoc::hardline())
-        .append(body)
+        .append(RcDoc::intersperse(lines, RcDoc::hardline()))
         .nest(2)
         .group()
         .append(RcDoc::hardline())
-        .append(RcDoc::text("endfunction"))
+        .append(RcDoc::text(end))
 }
 
-fn func_<'a>(name: &str, rtype: &str, prologue: RcDoc<'a>, stmt: RcDoc<'a>) -> RcDoc<'a> {
-    let body = RcDoc::concat(vec![prologue, RcDoc::hardline(), func_begin_end(stmt)]);
-    func_body(name, rtype, body)
+fn func_body<'a>(
+    lang: Language,
+    name: &str,
+    rtype: &str,
+    args: Vec<RcDoc<'a>>,
+    body: RcDoc<'a>,
+) -> RcDoc<'a> {
+    let header = RcDoc::text("function")
+        .append(RcDoc::space())
+        .append(RcDoc::as_string(rtype))
+        .append(RcDoc::space())
+        .append(RcDoc::as_string(name));
+    func_decl(lang, header, "endfunction", args, body)
 }
 
-fn func_read<'a>(name: &str, prologue: RcDoc<'a>, body: RcDoc<'a>) -> RcDoc<'a> {
+// force and release are procedural continuous assignments, which functions
+// cannot contain, so forceable signals are accessed through tasks
+fn task_<'a>(lang: Language, name: &str, args: Vec<RcDoc<'a>>, stmt: RcDoc<'a>) -> RcDoc<'a> {
+    let header = RcDoc::text("task")
+        .append(RcDoc::space())
+        .append(RcDoc::as_string(name));
+    func_decl(lang, header, "endtask", args, func_begin_end(stmt))
+}
+
+fn func_<'a>(
+    lang: Language,
+    name: &str,
+    rtype: &str,
+    args: Vec<RcDoc<'a>>,
+    vars: Vec<RcDoc<'a>>,
+    stmt: RcDoc<'a>,
+) -> RcDoc<'a> {
+    let mut lines = vars;
+    lines.push(func_begin_end(stmt));
+    func_body(
+        lang,
+        name,
+        rtype,
+        args,
+        RcDoc::intersperse(lines, RcDoc::hardline()),
+    )
+}
+
+fn func_read<'a>(
+    lang: Language,
+    name: &str,
+    args: Vec<RcDoc<'a>>,
+    vars: Vec<RcDoc<'a>>,
+    body: RcDoc<'a>,
+) -> RcDoc<'a> {
     let path = func_read_name(name);
-    func_(&path, "int", prologue, body)
+    func_(lang, &path, func_type(lang), args, vars, body)
 }
 
-fn func_write<'a>(name: &str, prologue: RcDoc<'a>, body: RcDoc<'a>) -> RcDoc<'a> {
+fn func_write<'a>(
+    lang: Language,
+    name: &str,
+    args: Vec<RcDoc<'a>>,
+    vars: Vec<RcDoc<'a>>,
+    body: RcDoc<'a>,
+) -> RcDoc<'a> {
     let path = func_write_name(name);
-    func_(&path, "void", prologue, body)
+    func_(lang, &path, "void", args, vars, body)
 }
 
-fn func_read_register<'a>(path: &str, width: u32) -> RcDoc<'a> {
+fn func_read_register<'a>(lang: Language, path: &str, width: u32) -> RcDoc<'a> {
     let var = "data";
     let sel = "sel";
-    let mut pvec = Vec::new();
+    let mut args = Vec::new();
+    let mut vars = Vec::new();
     let mut bvec = Vec::new();
-    let rval = RcDoc::concat(vec![RcDoc::as_string(&var), func_index_var(&sel)]);
-    let round_index = func_index(
-        RcDoc::as_string(0),
-        RcDoc::as_string(round_width(width.clone())),
-    );
-    let index = func_index(RcDoc::as_string(0), RcDoc::as_string(width.clone()));
-    let a = RcDoc::concat(vec![RcDoc::as_string(&var), round_index]);
-    let b = RcDoc::concat(vec![RcDoc::as_string(&var), index]);
-    pvec.push(func_arg(&sel));
-    pvec.push(func_var(&var, round_width(width.clone())));
-    bvec.push(func_assert_lt(&sel, max_sel(width.clone())));
+    let rval = RcDoc::concat(vec![RcDoc::as_string(var), func_index_var(sel)]);
+    let round_index = func_index(RcDoc::as_string(0), RcDoc::as_string(round_width(width)));
+    let index = func_index(RcDoc::as_string(0), RcDoc::as_string(width));
+    let a = RcDoc::concat(vec![RcDoc::as_string(var), round_index]);
+    let b = RcDoc::concat(vec![RcDoc::as_string(var), index]);
+    args.push(func_arg(lang, sel));
+    vars.push(func_var(lang, var, round_width(width)));
+    bvec.push(func_assert_lt(sel, max_sel(width)));
     bvec.push(func_assign(a, RcDoc::as_string(0)));
     bvec.push(func_assign(b, RcDoc::as_string(path)));
     bvec.push(func_return(rval));
-    let prologue = RcDoc::intersperse(pvec.into_iter(), RcDoc::hardline());
-    let body = RcDoc::intersperse(bvec.into_iter(), RcDoc::hardline());
-    func_read(path, prologue, body)
+    let body = RcDoc::intersperse(bvec, RcDoc::hardline());
+    func_read(lang, path, args, vars, body)
 }
 
-fn func_write_register<'a>(path: &str, width: u32) -> RcDoc<'a> {
+fn func_write_register<'a>(lang: Language, path: &str, width: u32) -> RcDoc<'a> {
     let var = "data";
     let sel = "sel";
     let val = "value";
-    let mut pvec = Vec::new();
+    let mut args = Vec::new();
+    let mut vars = Vec::new();
     let mut bvec = Vec::new();
-    let round_index = func_index(
-        RcDoc::as_string(0),
-        RcDoc::as_string(round_width(width.clone())),
-    );
-    let index = func_index(RcDoc::as_string(0), RcDoc::as_string(width.clone()));
-    let a = RcDoc::concat(vec![RcDoc::as_string(&var), round_index]);
-    let b = RcDoc::concat(vec![RcDoc::as_string(&var), index]);
-    let c = RcDoc::concat(vec![RcDoc::as_string(&var), func_index_var(&sel)]);
-    pvec.push(func_arg(&sel));
-    pvec.push(func_arg(&val));
-    pvec.push(func_var(&var, round_width(width.clone())));
-    bvec.push(func_assert_lt(&sel, max_sel(width.clone())));
+    let round_index = func_index(RcDoc::as_string(0), RcDoc::as_string(round_width(width)));
+    let index = func_index(RcDoc::as_string(0), RcDoc::as_string(width));
+    let a = RcDoc::concat(vec![RcDoc::as_string(var), round_index]);
+    let b = RcDoc::concat(vec![RcDoc::as_string(var), index]);
+    let c = RcDoc::concat(vec![RcDoc::as_string(var), func_index_var(sel)]);
+    args.push(func_arg(lang, sel));
+    args.push(func_arg(lang, val));
+    vars.push(func_var(lang, var, round_width(width)));
+    bvec.push(func_assert_lt(sel, max_sel(width)));
     bvec.push(func_assign(a, RcDoc::as_string(0)));
     bvec.push(func_assign(b.clone(), RcDoc::as_string(path)));
-    bvec.push(func_assign(c, RcDoc::as_string(&val)));
+    bvec.push(func_assign(c, RcDoc::as_string(val)));
     bvec.push(func_assign(RcDoc::as_string(path), b.clone()));
-    let prologue = RcDoc::intersperse(pvec.into_iter(), RcDoc::hardline());
-    let body = RcDoc::intersperse(bvec.into_iter(), RcDoc::hardline());
-    func_write(path, prologue, body)
+    let body = RcDoc::intersperse(bvec, RcDoc::hardline());
+    func_write(lang, path, args, vars, body)
+}
+
+// the value is kept in the DPI module, so a wide signal can be forced one
+// word at a time, every word forces the whole signal again
+fn func_force_signal<'a>(lang: Language, f: &Force) -> RcDoc<'a> {
+    let path = &f.path;
+    let width = f.width;
+    let var = force_value_name(path);
+    let sel = "sel";
+    let val = "value";
+    let mut args = Vec::new();
+    let mut bvec = Vec::new();
+    let index = func_index(RcDoc::as_string(0), RcDoc::as_string(width));
+    let a = RcDoc::concat(vec![RcDoc::as_string(&var), func_index_var(sel)]);
+    let b = RcDoc::concat(vec![RcDoc::as_string(&var), index]);
+    args.push(func_arg(lang, sel));
+    args.push(func_arg(lang, val));
+    bvec.push(func_assert_lt(sel, max_sel(width)));
+    bvec.push(func_assign(a, RcDoc::as_string(val)));
+    bvec.push(func_force_assign(RcDoc::as_string(path), b));
+    let body = RcDoc::intersperse(bvec, RcDoc::hardline());
+    task_(lang, &func_force_name(path), args, body)
 }
 
-fn func_read_memory<'a>(path: &str, width: u32) -> RcDoc<'a> {
+// after release a net follows its driver again, a variable keeps the forced
+// value until it is assigned
+fn func_release_signal<'a>(lang: Language, f: &Force) -> RcDoc<'a> {
+    let body = func_release_assign(RcDoc::as_string(&f.path));
+    task_(lang, &func_release_name(&f.path), Vec::new(), body)
+}
+
+fn func_read_memory<'a>(lang: Language, path: &str, width: u32) -> RcDoc<'a> {
     let var = "data";
     let sel = "sel";
     let addr = "addr";
-    let mut pvec = Vec::new();
+    let mut args = Vec::new();
+    let mut vars = Vec::new();
     let mut bvec = Vec::new();
-    let round_index = func_index(
-        RcDoc::as_string(0),
-        RcDoc::as_string(round_width(width.clone())),
-    );
-    let index = func_index(RcDoc::as_string(0), RcDoc::as_string(width.clone()));
+    let round_index = func_index(RcDoc::as_string(0), RcDoc::as_string(round_width(width)));
+    let index = func_index(RcDoc::as_string(0), RcDoc::as_string(width));
     let mem_addr = format!("{}[{}]", path, &addr);
-    let rval = RcDoc::concat(vec![RcDoc::as_string(&var), func_index_var(&sel)]);
-    let a = RcDoc::concat(vec![RcDoc::as_string(&var), round_index]);
-    let b = RcDoc::concat(vec![RcDoc::as_string(&var), index]);
-    pvec.push(func_arg(&addr));
-    pvec.push(func_arg(&sel));
-    pvec.push(func_var(&var, round_width(width.clone())));
-    bvec.push(func_assert_lt(&sel, max_sel(width.clone())));
+    let rval = RcDoc::concat(vec![RcDoc::as_string(var), func_index_var(sel)]);
+    let a = RcDoc::concat(vec![RcDoc::as_string(var), round_index]);
+    let b = RcDoc::concat(vec![RcDoc::as_string(var), index]);
+    args.push(func_arg(lang, addr));
+    args.push(func_arg(lang, sel));
+    vars.push(func_var(lang, var, round_width(width)));
+    bvec.push(func_assert_lt(sel, max_sel(width)));
     bvec.push(func_assign(a, RcDoc::as_string(0)));
     bvec.push(func_assign(b, RcDoc::as_string(mem_addr)));
     bvec.push(func_return(rval));
-    let prologue = RcDoc::intersperse(pvec.into_iter(), RcDoc::hardline());
-    let body = RcDoc::intersperse(bvec.into_iter(), RcDoc::hardline());
-    func_read(path, prologue, body)
+    let body = RcDoc::intersperse(bvec, RcDoc::hardline());
+    func_read(lang, path, args, vars, body)
 }
 
-fn func_write_memory<'a>(path: &str, width: u32) -> RcDoc<'a> {
+fn func_write_memory<'a>(lang: Language, path: &str, width: u32) -> RcDoc<'a> {
     let var = "data";
     let sel = "sel";
     let addr = "addr";
     let val = "value";
-    let mut pvec = Vec::new();
+    let mut args = Vec::new();
+    let mut vars = Vec::new();
     let mut bvec = Vec::new();
-    let round_index = func_index(
-        RcDoc::as_string(0),
-        RcDoc::as_string(round_width(width.clone())),
-    );
-    let index = func_index(RcDoc::as_string(0), RcDoc::as_string(width.clone()));
+    let round_index = func_index(RcDoc::as_string(0), RcDoc::as_string(round_width(width)));
+    let index = func_index(RcDoc::as_string(0), RcDoc::as_string(width));
     let mem_addr = format!("{}[{}]", path, &addr);
-    let a = RcDoc::concat(vec![RcDoc::as_string(&var), round_index]);
-    let b = RcDoc::concat(vec![RcDoc::as_string(&var), index]);
-    let c = RcDoc::concat(vec![RcDoc::as_string(&var), func_index_var(&sel)]);
-    pvec.push(func_arg(&addr));
-    pvec.push(func_arg(&sel));
-    pvec.push(func_arg(&val));
-    pvec.push(func_var(&var, round_width(width.clone())));
-    bvec.push(func_assert_lt(&sel, max_sel(width.clone())));
+    let a = RcDoc::concat(vec![RcDoc::as_string(var), round_index]);
+    let b = RcDoc::concat(vec![RcDoc::as_string(var), index]);
+    let c = RcDoc::concat(vec![RcDoc::as_string(var), func_index_var(sel)]);
+    args.push(func_arg(lang, addr));
+    args.push(func_arg(lang, sel));
+    args.push(func_arg(lang, val));
+    vars.push(func_var(lang, var, round_width(width)));
+    bvec.push(func_assert_lt(sel, max_sel(width)));
     bvec.push(func_assign(a, RcDoc::as_string(0)));
     bvec.push(func_assign(b.clone(), RcDoc::as_string(&mem_addr)));
-    bvec.push(func_assign(c, RcDoc::as_string(&val)));
+    bvec.push(func_assign(c, RcDoc::as_string(val)));
     bvec.push(func_assign(RcDoc::as_string(&mem_addr), b.clone()));
-    let prologue = RcDoc::intersperse(pvec.into_iter(), RcDoc::hardline());
-    let body = RcDoc::intersperse(bvec.into_iter(), RcDoc::hardline());
-    func_write(path, prologue, body)
+    let body = RcDoc::intersperse(bvec, RcDoc::hardline());
+    func_write(lang, path, args, vars, body)
 }
 
-fn func_read_signature<'a>(prefix: &'a str, args: &Vec<&str>) -> RcDoc<'a> {
-    let d = RcDoc::intersperse(args.iter().map(|i| RcDoc::as_string(i)), RcDoc::text(", "));
+fn func_read_signature<'a>(prefix: &'a str, args: &[&str]) -> RcDoc<'a> {
+    let d = RcDoc::intersperse(args.iter().map(RcDoc::as_string), RcDoc::text(", "));
     let func_name = func_read_name(prefix);
     RcDoc::as_string(func_name).append(func_paren(d))
 }
 
-fn func_write_signature<'a>(prefix: &'a str, args: &Vec<&str>) -> RcDoc<'a> {
-    let d = RcDoc::intersperse(args.iter().map(|i| RcDoc::as_string(i)), RcDoc::text(", "));
+fn func_write_signature<'a>(prefix: &'a str, args: &[&str]) -> RcDoc<'a> {
+    let d = RcDoc::intersperse(args.iter().map(RcDoc::as_string), RcDoc::text(", "));
     let func_name = func_write_name(prefix);
     RcDoc::as_string(func_name).append(func_paren(d))
 }
 
-fn func_switch_read_register<'a>(prefix: &str, reg: &'a Vec<Register>) -> RcDoc<'a> {
+fn func_force_signature<'a>(prefix: &'a str, args: &[&str]) -> RcDoc<'a> {
+    let d = RcDoc::intersperse(args.iter().map(RcDoc::as_string), RcDoc::text(", "));
+    let func_name = func_force_name(prefix);
+    RcDoc::as_string(func_name).append(func_paren(d))
+}
+
+fn func_release_signature<'a>(prefix: &'a str, args: &[&str]) -> RcDoc<'a> {
+    let d = RcDoc::intersperse(args.iter().map(RcDoc::as_string), RcDoc::text(", "));
+    let func_name = func_release_name(prefix);
+    RcDoc::as_string(func_name).append(func_paren(d))
+}
+
+fn func_switch_read_register<'a>(
+    lang: Language,
+    prefix: &str,
+    dispatch: Dispatch,
+    reg: &'a [Register],
+) -> RcDoc<'a> {
     let hid = "hid";
     let vargs = vec!["sel"];
-    let mut pvec = Vec::new();
-    let mut bvec = Vec::new();
-    pvec.push(func_arg(&hid));
+    let mut args = Vec::new();
+    args.push(func_arg(lang, hid));
     for v in vargs.iter() {
-        pvec.push(func_arg(v));
-    }
-    for (i, r) in reg.iter().enumerate() {
-        if i == 0 {
-            bvec.push(func_ifeq(
-                &hid,
-                r.hid,
-                func_return(func_read_signature(&r.path, &vargs)),
-            ));
-        } else {
-            bvec.push(func_else(func_ifeq(
-                &hid,
-                r.hid,
-                func_return(func_read_signature(&r.path, &vargs)),
-            )));
-        }
+        args.push(func_arg(lang, v));
     }
-    if reg.is_empty() {
-        bvec.push(func_error("there is no register declared"));
-    } else {
-        bvec.push(func_else(func_begin_end(func_error(
-            "wrong hid for reading register",
-        ))));
-    }
-    let prologue = RcDoc::intersperse(pvec.into_iter(), RcDoc::hardline());
-    let body = RcDoc::concat(bvec);
-    func_read(prefix, prologue, body)
+    let items = reg
+        .iter()
+        .map(|r| (r.hid, func_return(func_read_signature(&r.path, &vargs))))
+        .collect();
+    let body = func_dispatch(
+        lang,
+        dispatch,
+        hid,
+        items,
+        "there is no register declared",
+        "wrong hid for reading register",
+    );
+    func_read(lang, prefix, args, Vec::new(), body)
 }
 
-fn func_switch_write_register<'a>(prefix: &str, reg: &'a Vec<Register>) -> RcDoc<'a> {
+fn func_switch_write_register<'a>(
+    lang: Language,
+    prefix: &str,
+    dispatch: Dispatch,
+    reg: &'a [Register],
+) -> RcDoc<'a> {
     let hid = "hid";
     let vargs = vec!["sel", "value"];
-    let mut pvec = Vec::new();
-    let mut bvec = Vec::new();
-    pvec.push(func_arg(&hid));
+    let mut args = Vec::new();
+    args.push(func_arg(lang, hid));
     for v in vargs.iter() {
-        pvec.push(func_arg(v));
-    }
-    for (i, r) in reg.iter().enumerate() {
-        if i == 0 {
-            bvec.push(func_ifeq(
-                &hid,
-                r.hid,
-                func_eval(func_write_signature(&r.path, &vargs)),
-            ));
-        } else {
-            bvec.push(func_else(func_ifeq(
-                &hid,
-                r.hid,
-                func_eval(func_write_signature(&r.path, &vargs)),
-            )));
-        }
-    }
-    if reg.is_empty() {
-        bvec.push(func_error("there is no register declared"));
-    } else {
-        bvec.push(func_else(func_begin_end(func_error(
-            "wrong hid for writing register",
-        ))));
+        args.push(func_arg(lang, v));
     }
-    let prologue = RcDoc::intersperse(pvec.into_iter(), RcDoc::hardline());
-    let body = RcDoc::concat(bvec);
-    func_write(prefix, prologue, body)
+    let items = reg
+        .iter()
+        .map(|r| (r.hid, func_eval(func_write_signature(&r.path, &vargs))))
+        .collect();
+    let body = func_dispatch(
+        lang,
+        dispatch,
+        hid,
+        items,
+        "there is no register declared",
+        "wrong hid for writing register",
+    );
+    func_write(lang, prefix, args, Vec::new(), body)
 }
 
-fn func_switch_read_memory<'a>(prefix: &str, mem: &'a Vec<Memory>) -> RcDoc<'a> {
+fn func_switch_read_memory<'a>(
+    lang: Language,
+    prefix: &str,
+    dispatch: Dispatch,
+    mem: &'a [Memory],
+) -> RcDoc<'a> {
     let hid = "hid";
     let vargs = vec!["addr", "sel"];
-    let mut pvec = Vec::new();
-    let mut bvec = Vec::new();
-    pvec.push(func_arg(&hid));
+    let mut args = Vec::new();
+    args.push(func_arg(lang, hid));
     for v in vargs.iter() {
-        pvec.push(func_arg(v));
-    }
-    for (i, m) in mem.iter().enumerate() {
-        if i == 0 {
-            bvec.push(func_ifeq(
-                &hid,
-                m.hid,
-                func_return(func_read_signature(&m.path, &vargs)),
-            ));
-        } else {
-            bvec.push(func_else(func_ifeq(
-                &hid,
-                m.hid,
-                func_return(func_read_signature(&m.path, &vargs)),
-            )));
-        }
-    }
-    if mem.is_empty() {
-        bvec.push(func_error("there is no memory declared"));
-    } else {
-        bvec.push(func_else(func_begin_end(func_error(
-            "wrong hid for reading memory",
-        ))));
+        args.push(func_arg(lang, v));
     }
-    let prologue = RcDoc::intersperse(pvec.into_iter(), RcDoc::hardline());
-    let body = RcDoc::concat(bvec);
-    func_read(prefix, prologue, body)
+    let items = mem
+        .iter()
+        .map(|m| (m.hid, func_return(func_read_signature(&m.path, &vargs))))
+        .collect();
+    let body = func_dispatch(
+        lang,
+        dispatch,
+        hid,
+        items,
+        "there is no memory declared",
+        "wrong hid for reading memory",
+    );
+    func_read(lang, prefix, args, Vec::new(), body)
 }
 
-fn func_switch_write_memory<'a>(prefix: &str, mem: &'a Vec<Memory>) -> RcDoc<'a> {
+fn func_switch_write_memory<'a>(
+    lang: Language,
+    prefix: &str,
+    dispatch: Dispatch,
+    mem: &'a [Memory],
+) -> RcDoc<'a> {
     let hid = "hid";
     let vargs = vec!["addr", "sel", "value"];
-    let mut pvec = Vec::new();
-    let mut bvec = Vec::new();
-    pvec.push(func_arg(&hid));
+    let mut args = Vec::new();
+    args.push(func_arg(lang, hid));
     for v in vargs.iter() {
-        pvec.push(func_arg(v));
-    }
-    for (i, m) in mem.iter().enumerate() {
-        if i == 0 {
-            bvec.push(func_ifeq(
-                &hid,
-                m.hid,
-                func_eval(func_write_signature(&m.path, &vargs)),
-            ));
-        } else {
-            bvec.push(func_else(func_ifeq(
-                &hid,
-                m.hid,
-                func_eval(func_write_signature(&m.path, &vargs)),
-            )));
-        }
+        args.push(func_arg(lang, v));
+    }
+    let items = mem
+        .iter()
+        .map(|m| (m.hid, func_eval(func_write_signature(&m.path, &vargs))))
+        .collect();
+    let body = func_dispatch(
+        lang,
+        dispatch,
+        hid,
+        items,
+        "there is no memory declared",
+        "wrong hid for writing memory",
+    );
+    func_write(lang, prefix, args, Vec::new(), body)
+}
+
+fn func_switch_read_probe<'a>(
+    lang: Language,
+    prefix: &str,
+    dispatch: Dispatch,
+    probe: &'a [Probe],
+) -> RcDoc<'a> {
+    let hid = "hid";
+    let vargs = vec!["sel"];
+    let mut args = Vec::new();
+    args.push(func_arg(lang, hid));
+    for v in vargs.iter() {
+        args.push(func_arg(lang, v));
+    }
+    let items = probe
+        .iter()
+        .map(|p| (p.hid, func_return(func_read_signature(&p.path, &vargs))))
+        .collect();
+    let body = func_dispatch(
+        lang,
+        dispatch,
+        hid,
+        items,
+        "there is no probe declared",
+        "wrong hid for reading probe",
+    );
+    func_read(lang, prefix, args, Vec::new(), body)
+}
+
+fn func_switch_force<'a>(
+    lang: Language,
+    prefix: &str,
+    dispatch: Dispatch,
+    force: &'a [Force],
+) -> RcDoc<'a> {
+    let hid = "hid";
+    let vargs = vec!["sel", "value"];
+    let mut args = Vec::new();
+    args.push(func_arg(lang, hid));
+    for v in vargs.iter() {
+        args.push(func_arg(lang, v));
     }
-    if mem.is_empty() {
-        bvec.push(func_error("there is no memory declared"));
+    let items = force
+        .iter()
+        .map(|f| (f.hid, func_eval(func_force_signature(&f.path, &vargs))))
+        .collect();
+    let body = func_dispatch(
+        lang,
+        dispatch,
+        hid,
+        items,
+        "there is no forceable signal declared",
+        "wrong hid for forcing signal",
+    );
+    task_(lang, &func_force_name(prefix), args, body)
+}
+
+fn func_switch_release<'a>(
+    lang: Language,
+    prefix: &str,
+    dispatch: Dispatch,
+    force: &'a [Force],
+) -> RcDoc<'a> {
+    let hid = "hid";
+    let vargs = vec![];
+    let items = force
+        .iter()
+        .map(|f| (f.hid, func_eval(func_release_signature(&f.path, &vargs))))
+        .collect();
+    let body = func_dispatch(
+        lang,
+        dispatch,
+        hid,
+        items,
+        "there is no forceable signal declared",
+        "wrong hid for releasing signal",
+    );
+    let args = vec![func_arg(lang, hid)];
+    task_(lang, &func_release_name(prefix), args, body)
+}
+
+// values wider than 32 bits are passed as svBitVecVal arrays, LSB word
+// first. Every declaration has its own wide functions with an array of its
+// own width, lastlayer.cc.hbs calls them by hid with buffers of wide_words
+pub(crate) fn wide_words(width: u32) -> u32 {
+    round_width(width) / 32
+}
+
+fn func_wide_name(name: String, hid: u32) -> String {
+    format!("{}_wide_{}", name, hid)
+}
+
+fn func_read_wide<'a>(name: &str, addr: bool, path: &str, width: u32) -> RcDoc<'a> {
+    let val = "value";
+    let mut args = Vec::new();
+    if addr {
+        args.push(func_arg(Language::SystemVerilog, "addr"));
+    }
+    args.push(func_wide_arg("output", val, round_width(width)));
+    let src = if addr {
+        format!("{}[addr]", path)
     } else {
-        bvec.push(func_else(func_begin_end(func_error(
-            "wrong hid for writing memory",
-        ))));
+        path.to_string()
+    };
+    let index = func_index(RcDoc::as_string(0), RcDoc::as_string(width));
+    let lhs = RcDoc::concat(vec![RcDoc::as_string(val), index]);
+    let body = RcDoc::intersperse(
+        vec![
+            func_assign(RcDoc::as_string(val), RcDoc::as_string(0)),
+            func_assign(lhs, RcDoc::as_string(src)),
+        ],
+        RcDoc::hardline(),
+    );
+    func_(Language::SystemVerilog, name, "void", args, Vec::new(), body)
+}
+
+fn func_write_wide<'a>(name: &str, addr: bool, path: &str, width: u32) -> RcDoc<'a> {
+    let val = "value";
+    let mut args = Vec::new();
+    if addr {
+        args.push(func_arg(Language::SystemVerilog, "addr"));
     }
-    let prologue = RcDoc::intersperse(pvec.into_iter(), RcDoc::hardline());
-    let body = RcDoc::concat(bvec);
-    func_write(prefix, prologue, body)
+    args.push(func_wide_arg("input", val, round_width(width)));
+    let dst = if addr {
+        format!("{}[addr]", path)
+    } else {
+        path.to_string()
+    };
+    let index = func_index(RcDoc::as_string(0), RcDoc::as_string(width));
+    let rhs = RcDoc::concat(vec![RcDoc::as_string(val), index]);
+    let body = func_assign(RcDoc::as_string(dst), rhs);
+    func_(Language::SystemVerilog, name, "void", args, Vec::new(), body)
+}
+
+// same as func_force_signal with the whole value at once
+fn func_force_wide<'a>(name: &str, f: &Force) -> RcDoc<'a> {
+    let var = force_value_name(&f.path);
+    let val = "value";
+    let args = vec![func_wide_arg("input", val, round_width(f.width))];
+    let index = func_index(RcDoc::as_string(0), RcDoc::as_string(f.width));
+    let b = RcDoc::concat(vec![RcDoc::as_string(&var), index]);
+    let body = RcDoc::intersperse(
+        vec![
+            func_assign(RcDoc::as_string(&var), RcDoc::as_string(val)),
+            func_force_assign(RcDoc::as_string(&f.path), b),
+        ],
+        RcDoc::hardline(),
+    );
+    task_(Language::SystemVerilog, name, args, body)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn func_wide<'a>(
+    reg_name: &str,
+    mem_name: &str,
+    force_name: &str,
+    probe_name: &str,
+    reg: &[Register],
+    mem: &[Memory],
+    force: &[Force],
+    probe: &[Probe],
+) -> RcDoc<'a> {
+    let mut funcs = Vec::new();
+    let mut names = Vec::new();
+    let mut tasks = Vec::new();
+    for r in reg.iter() {
+        let read = func_wide_name(func_read_name(reg_name), r.hid);
+        let write = func_wide_name(func_write_name(reg_name), r.hid);
+        funcs.push(func_read_wide(&read, false, &r.path, r.width));
+        funcs.push(func_write_wide(&write, false, &r.path, r.width));
+        names.extend(vec![read, write]);
+    }
+    for m in mem.iter() {
+        let read = func_wide_name(func_read_name(mem_name), m.hid);
+        let write = func_wide_name(func_write_name(mem_name), m.hid);
+        funcs.push(func_read_wide(&read, true, &m.path, m.width));
+        funcs.push(func_write_wide(&write, true, &m.path, m.width));
+        names.extend(vec![read, write]);
+    }
+    for f in force.iter() {
+        let name = func_wide_name(func_force_name(force_name), f.hid);
+        funcs.push(func_force_wide(&name, f));
+        tasks.push(name);
+    }
+    for p in probe.iter() {
+        let name = func_wide_name(func_read_name(probe_name), p.hid);
+        funcs.push(func_read_wide(&name, false, &p.path, p.width));
+        names.push(name);
+    }
+    funcs.extend(names.iter().map(|n| func_export(n)));
+    funcs.extend(tasks.iter().map(|n| task_export(n)));
+    RcDoc::intersperse(funcs, RcDoc::hardline())
 }
 
 fn func_export<'a>(name: &str) -> RcDoc<'a> {
+    dpi_export("function", name)
+}
+
+fn task_export<'a>(name: &str) -> RcDoc<'a> {
+    dpi_export("task", name)
+}
+
+fn dpi_export<'a>(kind: &'a str, name: &str) -> RcDoc<'a> {
     RcDoc::text("export")
         .append(RcDoc::space())
         .append(func_str("DPI-C"))
         .append(RcDoc::space())
-        .append(RcDoc::text("function"))
+        .append(RcDoc::text(kind))
         .append(RcDoc::space())
         .append(RcDoc::as_string(name))
         .append(RcDoc::text(";"))
 }
 
-impl ToDoc for Register {
-    fn to_doc(&self) -> RcDoc<()> {
-        RcDoc::concat(vec![
-            func_read_register(&self.path, self.width.clone()),
-            RcDoc::hardline(),
-            func_write_register(&self.path, self.width.clone()),
-        ])
-    }
+fn register_doc<'a>(lang: Language, r: &Register) -> RcDoc<'a> {
+    RcDoc::concat(vec![
+        func_read_register(lang, &r.path, r.width),
+        RcDoc::hardline(),
+        func_write_register(lang, &r.path, r.width),
+    ])
 }
 
-impl ToDoc for Memory {
-    fn to_doc(&self) -> RcDoc<()> {
-        RcDoc::concat(vec![
-            func_read_memory(&self.path, self.width.clone()),
-            RcDoc::hardline(),
-            func_write_memory(&self.path, self.width.clone()),
-        ])
-    }
+fn memory_doc<'a>(lang: Language, m: &Memory) -> RcDoc<'a> {
+    RcDoc::concat(vec![
+        func_read_memory(lang, &m.path, m.width),
+        RcDoc::hardline(),
+        func_write_memory(lang, &m.path, m.width),
+    ])
+}
+
+fn probe_doc<'a>(lang: Language, p: &Probe) -> RcDoc<'a> {
+    func_read_register(lang, &p.path, p.width)
+}
+
+fn force_doc<'a>(lang: Language, f: &Force) -> RcDoc<'a> {
+    RcDoc::concat(vec![
+        func_var(lang, &force_value_name(&f.path), round_width(f.width)),
+        RcDoc::hardline(),
+        func_force_signal(lang, f),
+        RcDoc::hardline(),
+        func_release_signal(lang, f),
+    ])
 }
 
 impl ToDoc for LastLayer {
-    fn to_doc(&self) -> RcDoc<()> {
+    fn to_doc(&self) -> RcDoc<'_, ()> {
         match self {
-            LastLayer::AWIG(module_name, reg_name, mem_name, reg, mem) => {
+            LastLayer::Awig(
+                lang,
+                dispatch,
+                module_name,
+                reg_name,
+                mem_name,
+                force_name,
+                probe_name,
+                reg,
+                mem,
+                force,
+                probe,
+            ) => {
                 let mut doc = RcDoc::nil();
                 for r in reg.iter() {
-                    doc = doc.append(r.to_doc()).append(RcDoc::hardline());
+                    doc = doc.append(register_doc(*lang, r)).append(RcDoc::hardline());
                 }
                 for m in mem.iter() {
-                    doc = doc.append(m.to_doc()).append(RcDoc::hardline());
+                    doc = doc.append(memory_doc(*lang, m)).append(RcDoc::hardline());
+                }
+                for f in force.iter() {
+                    doc = doc.append(force_doc(*lang, f)).append(RcDoc::hardline());
+                }
+                for p in probe.iter() {
+                    doc = doc.append(probe_doc(*lang, p)).append(RcDoc::hardline());
                 }
                 doc = doc.append(RcDoc::concat(vec![
-                    func_switch_read_register(reg_name, reg),
+                    func_switch_read_register(*lang, reg_name, *dispatch, reg),
+                    RcDoc::hardline(),
+                    func_switch_write_register(*lang, reg_name, *dispatch, reg),
+                    RcDoc::hardline(),
+                    func_switch_read_memory(*lang, mem_name, *dispatch, mem),
                     RcDoc::hardline(),
-                    func_switch_write_register(reg_name, reg),
+                    func_switch_write_memory(*lang, mem_name, *dispatch, mem),
                     RcDoc::hardline(),
-                    func_switch_read_memory(mem_name, mem),
+                    func_switch_force(*lang, force_name, *dispatch, force),
                     RcDoc::hardline(),
-                    func_switch_write_memory(mem_name, mem),
+                    func_switch_release(*lang, force_name, *dispatch, force),
+                    RcDoc::hardline(),
+                    func_switch_read_probe(*lang, probe_name, *dispatch, probe),
                 ]));
                 doc = doc.append(RcDoc::hardline());
                 doc = doc.append(func_export(&func_read_name(reg_name)));
@@ -549,63 +959,570 @@ impl ToDoc for LastLayer {
                 doc = doc.append(func_export(&func_read_name(mem_name)));
                 doc = doc.append(RcDoc::hardline());
                 doc = doc.append(func_export(&func_write_name(mem_name)));
+                doc = doc.append(RcDoc::hardline());
+                doc = doc.append(task_export(&func_force_name(force_name)));
+                doc = doc.append(RcDoc::hardline());
+                doc = doc.append(task_export(&func_release_name(force_name)));
+                doc = doc.append(RcDoc::hardline());
+                doc = doc.append(func_export(&func_read_name(probe_name)));
+                let wide =
+                    !(reg.is_empty() && mem.is_empty() && force.is_empty() && probe.is_empty());
+                if *lang == Language::SystemVerilog && wide {
+                    doc = doc.append(RcDoc::hardline());
+                    doc = doc.append(func_wide(
+                        reg_name, mem_name, force_name, probe_name, reg, mem, force, probe,
+                    ));
+                }
                 func_module(module_name, doc)
             }
         }
     }
 }
 
-fn check_register_hid(reg: &Vec<Register>) -> bool {
-    let mut map: HashSet<u32> = HashSet::new();
-    for r in reg.iter() {
-        if !map.contains(&r.hid) {
-            map.insert(r.hid);
-        } else {
-            panic!("register hid already exists");
+// AwigError is returned by compile for declarations that would generate
+// broken or ambiguous DPI functions
+#[derive(Debug)]
+pub enum AwigError {
+    DuplicateHid(&'static str, u32),
+    DuplicatePath(&'static str, String),
+    DuplicateFunction(&'static str, String, &'static str, String),
+    ZeroWidth(&'static str, String),
+    HidOutOfRange(&'static str, u32),
+    SparseHid(&'static str, u32),
+    BadPath(&'static str, String),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for AwigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AwigError::DuplicateHid(kind, hid) => write!(f, "{} hid {} already exists", kind, hid),
+            AwigError::DuplicatePath(kind, path) => write!(f, "{} {} already exists", kind, path),
+            AwigError::DuplicateFunction(kind, path, other_kind, other_path) => write!(
+                f,
+                "{} {} and {} {} generate the same DPI function name",
+                kind, path, other_kind, other_path
+            ),
+            AwigError::ZeroWidth(kind, path) => write!(f, "{} {} has zero width", kind, path),
+            AwigError::HidOutOfRange(kind, hid) => {
+                write!(f, "{} hid {} does not fit in a C int", kind, hid)
+            }
+            AwigError::SparseHid(kind, hid) => {
+                write!(f, "{} hid {} is not dense, hids must be 0 to n-1", kind, hid)
+            }
+            AwigError::BadPath(kind, path) => {
+                write!(f, "{} path {:?} is not a hierarchical name", kind, path)
+            }
+            AwigError::Io(e) => write!(f, "failed to write DPI module: {}", e),
         }
     }
-    true
 }
 
-fn check_memory_hid(mem: &Vec<Memory>) -> bool {
-    let mut map: HashSet<u32> = HashSet::new();
-    for m in mem.iter() {
-        if !map.contains(&m.hid) {
-            map.insert(m.hid);
-        } else {
-            panic!("memory hid already exists");
+impl Error for AwigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AwigError::Io(e) => Some(e),
+            _ => None,
         }
     }
-    true
 }
 
+impl From<std::io::Error> for AwigError {
+    fn from(e: std::io::Error) -> AwigError {
+        AwigError::Io(e)
+    }
+}
+
+// hierarchical path below the top module, e.g. "adder.a" or "cpu.rf.regs[0]"
+fn is_hier_path(path: &str) -> bool {
+    !path.is_empty()
+        && path.split('.').all(|name| {
+            let mut chars = name.chars();
+            match chars.next() {
+                Some(c) if c.is_ascii_alphabetic() || c == '_' => chars
+                    .all(|c| c.is_ascii_alphanumeric() || "_$[]".contains(c)),
+                _ => false,
+            }
+        })
+}
+
+// hids are passed as int through the C API, Build::validate runs the same
+// checks before anything is generated and reports every error found
+pub(crate) fn check_declarations<'a, I>(
+    kind: &'static str,
+    dispatch: Dispatch,
+    decls: I,
+) -> Vec<AwigError>
+where
+    I: ExactSizeIterator<Item = (u32, &'a str, u32)>,
+{
+    let mut errors = Vec::new();
+    let mut hids: HashSet<u32> = HashSet::new();
+    let mut paths: HashSet<&str> = HashSet::new();
+    let count = decls.len();
+    for (hid, path, width) in decls {
+        if hid > i32::MAX as u32 {
+            errors.push(AwigError::HidOutOfRange(kind, hid));
+        } else if dispatch == Dispatch::Dense && hid as usize >= count {
+            // unique hids below count are exactly 0..count-1
+            errors.push(AwigError::SparseHid(kind, hid));
+        }
+        if !hids.insert(hid) {
+            errors.push(AwigError::DuplicateHid(kind, hid));
+        }
+        if !paths.insert(path) {
+            errors.push(AwigError::DuplicatePath(kind, path.to_string()));
+        }
+        if !is_hier_path(path) {
+            errors.push(AwigError::BadPath(kind, path.to_string()));
+        }
+        if width == 0 {
+            errors.push(AwigError::ZeroWidth(kind, path.to_string()));
+        }
+    }
+    errors
+}
+
+// functions and variables are named after the mangled path, so a register
+// and a probe on the same path, or paths like r[0] and r_0_, would generate
+// the same name twice. Duplicate paths of one kind are DuplicatePath
+pub(crate) fn check_function_names(
+    reg: &[Register],
+    mem: &[Memory],
+    force: &[Force],
+    probe: &[Probe],
+) -> Vec<AwigError> {
+    let reg = reg
+        .iter()
+        .map(|r| ("register", &r.path, vec![func_read_name(&r.path), func_write_name(&r.path)]));
+    let mem = mem
+        .iter()
+        .map(|m| ("memory", &m.path, vec![func_read_name(&m.path), func_write_name(&m.path)]));
+    let force = force.iter().map(|f| {
+        let names = vec![
+            func_force_name(&f.path),
+            func_release_name(&f.path),
+            force_value_name(&f.path),
+        ];
+        ("forceable signal", &f.path, names)
+    });
+    let probe = probe
+        .iter()
+        .map(|p| ("probe", &p.path, vec![func_read_name(&p.path)]));
+    let mut errors = Vec::new();
+    let mut names: HashMap<String, (&'static str, &String)> = HashMap::new();
+    for (kind, path, funcs) in reg.chain(mem).chain(force).chain(probe) {
+        for name in funcs {
+            match names.get(&name) {
+                Some(&(k, p)) if k == kind && p == path => (),
+                Some(&(k, p)) => {
+                    errors.push(AwigError::DuplicateFunction(kind, path.clone(), k, p.clone()));
+                    break;
+                }
+                None => {
+                    names.insert(name, (kind, path));
+                }
+            }
+        }
+    }
+    errors
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn compile(
     path: &Path,
     top_name: &str,
     module_name: &str,
     reg_func_prefix: &str,
     mem_func_prefix: &str,
-    reg: &Vec<Register>,
-    mem: &Vec<Memory>,
-) -> std::io::Result<()> {
-    check_register_hid(reg);
-    check_memory_hid(mem);
-    let mut llreg = reg.clone();
+    force_func_prefix: &str,
+    probe_func_prefix: &str,
+    reg: &[Register],
+    mem: &[Memory],
+    force: &[Force],
+    probe: &[Probe],
+    dispatch: Dispatch,
+    lang: Language,
+) -> Result<(), AwigError> {
+    let errors = vec![
+        check_declarations(
+            "register",
+            dispatch,
+            reg.iter().map(|r| (r.hid, r.path.as_str(), r.width)),
+        ),
+        check_declarations(
+            "memory",
+            dispatch,
+            mem.iter().map(|m| (m.hid, m.path.as_str(), m.width)),
+        ),
+        check_declarations(
+            "forceable signal",
+            dispatch,
+            force.iter().map(|f| (f.hid, f.path.as_str(), f.width)),
+        ),
+        check_declarations(
+            "probe",
+            dispatch,
+            probe.iter().map(|p| (p.hid, p.path.as_str(), p.width)),
+        ),
+        check_function_names(reg, mem, force, probe),
+    ];
+    if let Some(e) = errors.into_iter().flatten().next() {
+        return Err(e);
+    }
+    let mut llreg = reg.to_vec();
     for (l, r) in llreg.iter_mut().zip(reg.iter()) {
         l.path = format!("{}.{}", top_name, r.path);
     }
-    let mut llmem = mem.clone();
+    let mut llmem = mem.to_vec();
     for (l, m) in llmem.iter_mut().zip(mem.iter()) {
         l.path = format!("{}.{}", top_name, m.path);
     }
-    let awig = LastLayer::AWIG(
+    let mut llforce = force.to_vec();
+    for (l, f) in llforce.iter_mut().zip(force.iter()) {
+        l.path = format!("{}.{}", top_name, f.path);
+    }
+    let mut llprobe = probe.to_vec();
+    for (l, p) in llprobe.iter_mut().zip(probe.iter()) {
+        l.path = format!("{}.{}", top_name, p.path);
+    }
+    let awig = LastLayer::Awig(
+        lang,
+        dispatch,
         module_name.to_string(),
         reg_func_prefix.to_string(),
         mem_func_prefix.to_string(),
+        force_func_prefix.to_string(),
+        probe_func_prefix.to_string(),
         llreg,
         llmem,
+        llforce,
+        llprobe,
     );
     let mut file = File::create(path)?;
     file.write_all(awig.to_pretty().as_bytes())?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn reg(hid: u32, path: &str, width: u32) -> Register {
+        Register {
+            hid,
+            path: path.to_string(),
+            width,
+        }
+    }
+
+    fn mem(hid: u32, path: &str, width: u32) -> Memory {
+        Memory {
+            hid,
+            path: path.to_string(),
+            width,
+        }
+    }
+
+    // unique per test thread and process, tests run concurrently
+    fn temp_path() -> PathBuf {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let n = NEXT.fetch_add(1, Ordering::SeqCst);
+        env::temp_dir().join(format!("awig_test_{}_{}.v", std::process::id(), n))
+    }
+
+    fn compile_with(
+        reg: Vec<Register>,
+        mem: Vec<Memory>,
+        dispatch: Dispatch,
+        lang: Language,
+    ) -> Result<String, AwigError> {
+        compile_all(reg, mem, Vec::new(), Vec::new(), dispatch, lang)
+    }
+
+    fn compile_all(
+        reg: Vec<Register>,
+        mem: Vec<Memory>,
+        force: Vec<Force>,
+        probe: Vec<Probe>,
+        dispatch: Dispatch,
+        lang: Language,
+    ) -> Result<String, AwigError> {
+        let path = temp_path();
+        let res = compile(
+            &path,
+            "top",
+            "top_dpi",
+            "dpi_reg",
+            "dpi_mem",
+            "dpi_sig",
+            "dpi_probe",
+            &reg,
+            &mem,
+            &force,
+            &probe,
+            dispatch,
+            lang,
+        );
+        let out = res.map(|_| std::fs::read_to_string(&path).unwrap());
+        let _ = std::fs::remove_file(&path);
+        out
+    }
+
+    #[test]
+    fn declaration_errors() {
+        let cases = vec![
+            (
+                vec![reg(0, "a", 8), reg(0, "b", 8)],
+                vec![],
+                Dispatch::Case,
+                "register hid 0 already exists",
+            ),
+            (
+                vec![],
+                vec![mem(0, "m", 8), mem(1, "m", 8)],
+                Dispatch::Case,
+                "memory m already exists",
+            ),
+            (
+                vec![reg(0, "a", 0)],
+                vec![],
+                Dispatch::Case,
+                "register a has zero width",
+            ),
+            (
+                vec![reg(i32::MAX as u32 + 1, "a", 8)],
+                vec![],
+                Dispatch::Case,
+                "register hid 2147483648 does not fit in a C int",
+            ),
+            (
+                vec![reg(0, "a.1b", 8)],
+                vec![],
+                Dispatch::Case,
+                "register path \"a.1b\" is not a hierarchical name",
+            ),
+            (
+                vec![reg(0, "a", 8), reg(2, "b", 8)],
+                vec![],
+                Dispatch::Dense,
+                "register hid 2 is not dense, hids must be 0 to n-1",
+            ),
+        ];
+        for (reg, mem, dispatch, error) in cases {
+            let res = compile_with(reg, mem, dispatch, Language::Verilog);
+            assert_eq!(res.unwrap_err().to_string(), error);
+        }
+    }
+
+    #[test]
+    fn duplicate_function_names() {
+        let probe = |path: &str| Probe {
+            hid: 0,
+            path: path.to_string(),
+            width: 8,
+        };
+        let cases = vec![
+            (
+                vec![reg(0, "r", 8)],
+                vec![],
+                vec![probe("r")],
+                "probe r and register r generate the same DPI function name",
+            ),
+            (
+                vec![reg(0, "r", 8)],
+                vec![mem(0, "r", 8)],
+                vec![],
+                "memory r and register r generate the same DPI function name",
+            ),
+            (
+                vec![reg(0, "a.b_c", 8), reg(1, "a_b.c", 8)],
+                vec![],
+                vec![],
+                "register a_b.c and register a.b_c generate the same DPI function name",
+            ),
+            (
+                vec![reg(0, "r[0]", 8)],
+                vec![],
+                vec![probe("r_0_")],
+                "probe r_0_ and register r[0] generate the same DPI function name",
+            ),
+        ];
+        for (reg, mem, probe, error) in cases {
+            let res = compile_all(reg, mem, Vec::new(), probe, Dispatch::Case, Language::Verilog);
+            assert_eq!(res.unwrap_err().to_string(), error);
+        }
+        let force = vec![Force {
+            hid: 0,
+            path: "r".to_string(),
+            width: 8,
+        }];
+        let res = compile_all(
+            vec![reg(0, "r", 8)],
+            Vec::new(),
+            force,
+            Vec::new(),
+            Dispatch::Case,
+            Language::Verilog,
+        );
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn generated_functions() {
+        let cases = vec![
+            (
+                vec![reg(0, "a", 8), reg(1, "b", 8)],
+                vec![mem(0, "m", 32)],
+                Dispatch::Case,
+                Language::Verilog,
+                vec!["function int dpi_reg_read;", "function void dpi_mem_write;"],
+                vec![],
+            ),
+            (
+                vec![reg(i32::MAX as u32, "a", 8)],
+                vec![],
+                Dispatch::Case,
+                Language::Verilog,
+                vec!["2147483647: return top_a_read(sel);"],
+                vec![],
+            ),
+            (
+                vec![reg(0, "a", 8), reg(7, "b", 8)],
+                vec![],
+                Dispatch::Case,
+                Language::Verilog,
+                vec![
+                    "case (hid)",
+                    "7: return top_b_read(sel);",
+                    "default: $error(\"wrong hid for reading register\");",
+                ],
+                vec!["if (hid"],
+            ),
+            (
+                vec![reg(2, "c", 8), reg(0, "a", 8), reg(1, "b", 8)],
+                vec![],
+                Dispatch::Dense,
+                Language::Verilog,
+                vec!["case (hid[2-1:0])", "2'd2: return top_c_read(sel);"],
+                vec![],
+            ),
+            (
+                vec![reg(0, "a", 8), reg(1, "b", 40)],
+                vec![mem(0, "m", 64)],
+                Dispatch::Case,
+                Language::SystemVerilog,
+                vec![
+                    "function int unsigned top_a_read(input int unsigned sel);",
+                    "bit [64-1:0] data;",
+                    "unique case (hid)",
+                    "function void dpi_reg_read_wide_0(output bit [32-1:0] value);",
+                    "function void dpi_reg_read_wide_1(output bit [64-1:0] value);",
+                    "value[0+:40] = top.b;",
+                    "top.m[addr] = value[0+:64];",
+                    "export \"DPI-C\" function dpi_mem_write_wide_0;",
+                ],
+                vec!["integer"],
+            ),
+        ];
+        for (reg, mem, dispatch, lang, present, absent) in cases {
+            let out = compile_with(reg, mem, dispatch, lang).unwrap();
+            for line in present {
+                assert!(out.contains(line), "missing {:?} in\n{}", line, out);
+            }
+            for line in absent {
+                assert!(!out.contains(line), "unexpected {:?} in\n{}", line, out);
+            }
+        }
+    }
+
+    #[test]
+    fn wide_force_and_probe() {
+        let force = vec![Force {
+            hid: 3,
+            path: "f".to_string(),
+            width: 70,
+        }];
+        let probe = vec![Probe {
+            hid: 2,
+            path: "p".to_string(),
+            width: 33,
+        }];
+        let out = compile_all(
+            Vec::new(),
+            Vec::new(),
+            force,
+            probe,
+            Dispatch::Case,
+            Language::SystemVerilog,
+        )
+        .unwrap();
+        for line in [
+            "task dpi_sig_force_wide_3(input bit [96-1:0] value);",
+            "top_f_force_value = value;",
+            "force top.f = top_f_force_value[0+:70];",
+            "function void dpi_probe_read_wide_2(output bit [64-1:0] value);",
+            "value[0+:33] = top.p;",
+            "export \"DPI-C\" task dpi_sig_force_wide_3;",
+            "export \"DPI-C\" function dpi_probe_read_wide_2;",
+        ] {
+            assert!(out.contains(line), "missing {:?} in\n{}", line, out);
+        }
+        let out = compile_with(Vec::new(), Vec::new(), Dispatch::Case, Language::Verilog).unwrap();
+        assert!(!out.contains("wide"));
+    }
+
+    // a net driven by combinational logic is overridden with force and
+    // release, a plain assignment would be rejected as PROCASSWIRE
+    #[test]
+    fn force_wire_target() {
+        let force = vec![Force {
+            hid: 0,
+            path: "u.valid".to_string(),
+            width: 1,
+        }];
+        for lang in [Language::Verilog, Language::SystemVerilog] {
+            let out = compile_all(
+                Vec::new(),
+                Vec::new(),
+                force.clone(),
+                Vec::new(),
+                Dispatch::Case,
+                lang,
+            )
+            .unwrap();
+            for line in [
+                "top_u_valid_force_value[sel*32+:32] = value;",
+                "force top.u.valid = top_u_valid_force_value[0+:1];",
+                "release top.u.valid;",
+                "0: top_u_valid_force(sel, value);",
+                "0: top_u_valid_release();",
+                "endtask",
+                "export \"DPI-C\" task dpi_sig_force;",
+                "export \"DPI-C\" task dpi_sig_release;",
+            ] {
+                assert!(out.contains(line), "missing {:?} in\n{}", line, out);
+            }
+            for line in ["function void top_u_valid", "_apply"] {
+                assert!(!out.contains(line), "unexpected {:?} in\n{}", line, out);
+            }
+            let assign = out.lines().any(|l| l.trim_start().starts_with("top.u.valid ="));
+            assert!(!assign, "unexpected assignment in\n{}", out);
+        }
+        let out = compile_all(
+            Vec::new(),
+            Vec::new(),
+            force,
+            Vec::new(),
+            Dispatch::Case,
+            Language::Verilog,
+        )
+        .unwrap();
+        for line in ["task top_u_valid_force;", "task dpi_sig_force;", "task top_u_valid_release;"] {
+            assert!(out.contains(line), "missing {:?} in\n{}", line, out);
+        }
+    }
+}
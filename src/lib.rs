@@ -1,29 +1,249 @@
-use crate::util::{get_lastlayer_root_dir, run_cmd};
+use crate::util::{
+    create_dir, get_lastlayer_root_dir, rename, symlink_dir, write_file, FsError,
+};
 use handlebars::Handlebars;
 use serde::Serialize;
+use std::collections::BTreeMap;
+use std::env;
 use std::error::Error;
-use std::fs::File;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
 use glob::glob;
 
 pub mod awig;
+pub mod runtime;
 pub mod util;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct Register {
     pub hid: u32,
     pub path: String,
     pub width: u32,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct Memory {
     pub hid: u32,
     pub path: String,
     pub width: u32,
 }
 
+#[derive(Clone, Debug, Serialize)]
+pub struct Probe {
+    pub hid: u32,
+    pub path: String,
+    pub width: u32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Force {
+    pub hid: u32,
+    pub path: String,
+    pub width: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TraceFormat {
+    Vcd,
+    Fst,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Profile {
+    Debug,
+    Release,
+}
+
+// Shared builds lib<name>.so, Static builds lib<name>.a for linking with
+// cargo, Executable builds <name> with a main that resets and runs the design
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Output {
+    Shared,
+    Static,
+    Executable { reset: u32, run: u32 },
+}
+
+const MIN_VERILATOR_VERSION: (u32, u32) = (4, 24);
+
+// Verilator 5 models are always threaded and need C++14, the runtime sources
+// and flags used here only match 4.x
+const MAX_VERILATOR_MAJOR: u32 = 4;
+
+// first release implementing procedural force and release
+const FORCE_VERILATOR_VERSION: (u32, u32) = (4, 220);
+
+// default templates and the header are embedded, so the crate does not need
+// its source dir at runtime, e.g. when used from the cargo registry
+const TEMPLATES: [(&str, &str); 4] = [
+    ("lastlayer.v.hbs", include_str!("handlebars/lastlayer.v.hbs")),
+    ("lastlayer.cc.hbs", include_str!("handlebars/lastlayer.cc.hbs")),
+    ("lastlayer_main.cc.hbs", include_str!("handlebars/lastlayer_main.cc.hbs")),
+    ("lastlayer.rs.hbs", include_str!("handlebars/lastlayer.rs.hbs")),
+];
+
+const HEADER: &str = include_str!("../include/lastlayer/lastlayer.h");
+
+// environment variables read by compile
+const ENV_VARS: [&str; 6] = ["VERILATOR", "VERILATOR_ROOT", "CXX", "AR", "CXXSTDLIB", "NUM_JOBS"];
+
+// parse output of verilator --version, e.g. "Verilator 4.024 2019-12-08 rev v4.024"
+fn parse_verilator_version(version: &str) -> Option<(u32, u32)> {
+    let number = version.split_whitespace().nth(1)?;
+    let mut parts = number.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+// files below dir, e.g. headers included as "sub/defs.vh", symlinked
+// directories are not followed so a link cycle cannot recurse forever
+fn list_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            match entry.file_type() {
+                Ok(t) if t.is_dir() => dirs.push(path),
+                _ if path.is_file() => files.push(path),
+                _ => (),
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+// FNV-1a, fingerprints and object names are kept on disk, so unlike
+// DefaultHasher and the std Hash impls the result must not change between
+// Rust releases
+struct StableHasher(u64);
+
+impl StableHasher {
+    fn new() -> StableHasher {
+        StableHasher(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes.iter() {
+            self.0 ^= u64::from(*b);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    // length prefixed, so consecutive fields cannot run into each other
+    fn field<T: AsRef<[u8]>>(&mut self, value: T) -> &mut StableHasher {
+        let bytes = value.as_ref();
+        self.write(&(bytes.len() as u64).to_le_bytes());
+        self.write(bytes);
+        self
+    }
+
+    fn path(&mut self, path: &Path) -> &mut StableHasher {
+        self.field(path.to_string_lossy().as_bytes())
+    }
+
+    fn list<I, T>(&mut self, values: I) -> &mut StableHasher
+    where
+        I: ExactSizeIterator<Item = T>,
+        T: AsRef<[u8]>,
+    {
+        self.write(&(values.len() as u64).to_le_bytes());
+        for v in values {
+            self.field(v);
+        }
+        self
+    }
+
+    fn paths<'a, I>(&mut self, paths: I) -> &mut StableHasher
+    where
+        I: ExactSizeIterator<Item = &'a PathBuf>,
+    {
+        self.list(paths.map(|p| p.to_string_lossy().into_owned()))
+    }
+
+    fn finish(&self) -> String {
+        format!("{:016x}", self.0)
+    }
+}
+
+// the C++ compiler with its profile flags, resolved once per compile since
+// the cc crate probes the environment on every lookup
+#[derive(Clone, Debug)]
+struct Compiler {
+    path: PathBuf,
+    profile_flags: Vec<String>,
+}
+
+// ValidationError holds every problem found by Build::validate
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValidationError {
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid build configuration")?;
+        for problem in self.problems.iter() {
+            write!(f, "\n  {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ValidationError {}
+
+fn check_file(problems: &mut Vec<String>, kind: &str, file: &Path) {
+    if file.to_str().is_none() {
+        problems.push(format!("{} {:?} is not valid UTF-8", kind, file));
+    } else if !file.is_file() {
+        problems.push(format!("{} {} not found", kind, file.display()));
+    }
+}
+
+fn check_dir(problems: &mut Vec<String>, kind: &str, dir: &Path) {
+    if dir.to_str().is_none() {
+        problems.push(format!("{} {:?} is not valid UTF-8", kind, dir));
+    } else if !dir.is_dir() {
+        problems.push(format!("{} {} is not a directory", kind, dir.display()));
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Parameter {
+    pub name: String,
+    pub value: String,
+}
+
+// artifacts.json, lists what compile produced for downstream tooling
+#[derive(Serialize)]
+struct Artifacts {
+    name: String,
+    output: String,
+    library: PathBuf,
+    header: PathBuf,
+    bindings: Option<PathBuf>,
+    verilog: Vec<PathBuf>,
+    sources: Vec<PathBuf>,
+    registers: Vec<Register>,
+    memories: Vec<Memory>,
+    forces: Vec<Force>,
+    probes: Vec<Probe>,
+    verilator_version: String,
+    cxx: PathBuf,
+    cxx_version: String,
+}
+
 pub struct Build {
     tool_name: String,
     virtual_top_name: Option<String>,
@@ -33,17 +253,61 @@ pub struct Build {
     verilog_warnings: Vec<String>,
     verilog_files: Vec<PathBuf>,
     verilog_include_dirs: Vec<PathBuf>,
+    verilog_lib_dirs: Vec<PathBuf>,
+    verilog_lib_files: Vec<PathBuf>,
+    verilog_defines: Vec<(String, Option<String>)>,
+    verilog_params: Vec<Parameter>,
+    verilator_args: Vec<String>,
     cc_flags: Vec<String>,
     cc_include_dirs: Vec<PathBuf>,
     cc_link_dirs: Vec<PathBuf>,
     cc_link_libs: Vec<String>,
     cc_files: Vec<PathBuf>,
+    verilator_cc_files: Vec<PathBuf>,
+    cache_dir: Option<PathBuf>,
+    jobs: Option<usize>,
+    threads: Option<u32>,
+    cxx: Option<PathBuf>,
+    profile: Option<Profile>,
+    output: Output,
     out_dir: Option<PathBuf>,
     vcd_file: Option<String>,
+    trace: bool,
+    trace_format: TraceFormat,
+    savable: bool,
+    dense_hids: bool,
+    systemverilog: bool,
+    force_rebuild: bool,
+    verilator_include_link: bool,
+    rust_bindings: bool,
+    cargo_metadata: bool,
+    log: Mutex<Option<File>>,
     handlebars_dir: Option<PathBuf>,
+    templates: Vec<(String, String)>,
+    template_vars: BTreeMap<String, String>,
     bin: Option<PathBuf>,
+    verilator_root: Option<PathBuf>,
     reg: Vec<Register>,
     mem: Vec<Memory>,
+    force: Vec<Force>,
+    probe: Vec<Probe>,
+}
+
+// hid and size in 32-bit words of a declaration, lastlayer.cc.hbs sizes the
+// buffer of each wide DPI function with it
+#[derive(Serialize)]
+struct Wide {
+    hid: u32,
+    words: u32,
+}
+
+impl Wide {
+    fn new(hid: u32, width: u32) -> Wide {
+        Wide {
+            hid,
+            words: awig::wide_words(width),
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -53,6 +317,25 @@ struct VirtualHandle {
     clock: String,
     reset: String,
     vcd_file: String,
+    params: Vec<Parameter>,
+    reset_cycles: u32,
+    run_cycles: u32,
+    reg: Vec<Register>,
+    mem: Vec<Memory>,
+    force: Vec<Force>,
+    probe: Vec<Probe>,
+    systemverilog: bool,
+    reg_wide: Vec<Wide>,
+    mem_wide: Vec<Wide>,
+    force_wide: Vec<Wide>,
+    probe_wide: Vec<Wide>,
+    vars: BTreeMap<String, String>,
+}
+
+impl Default for Build {
+    fn default() -> Build {
+        Build::new()
+    }
 }
 
 impl Build {
@@ -98,13 +381,122 @@ impl Build {
         }
     }
 
-    fn get_handlebars_dir(&self) -> PathBuf {
-        match &self.handlebars_dir {
-            Some(d) => d.to_path_buf(),
-            None => panic!("handlebars dir not defined"),
+    fn has_trace(&self) -> bool {
+        self.trace || self.vcd_file.is_some()
+    }
+
+    // the default is per user, objects from a shared dir would be linked
+    // into the design, without a cache home the objects stay in out_dir
+    fn get_cache_dir(&self) -> PathBuf {
+        if let Some(d) = &self.cache_dir {
+            return d.to_path_buf();
+        }
+        let home = env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .filter(|d| d.is_absolute())
+            .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")));
+        match home {
+            Some(d) => d.join("lastlayer"),
+            None => self.get_out_dir().join("cache"),
+        }
+    }
+
+    fn get_jobs(&self) -> usize {
+        if let Some(n) = self.jobs {
+            return n.max(1);
+        }
+        // cargo sets NUM_JOBS when running build scripts
+        match env::var("NUM_JOBS").ok().and_then(|n| n.parse::<usize>().ok()) {
+            Some(n) => n.max(1),
+            None => thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        }
+    }
+
+    #[cfg(feature = "cc")]
+    fn get_cc_tool(&self) -> Option<cc::Tool> {
+        cc::Build::new()
+            .cpp(true)
+            .cargo_metadata(false)
+            .try_get_compiler()
+            .ok()
+    }
+
+    fn get_compiler(&self) -> Compiler {
+        let path = self.cxx.clone().or_else(|| env::var_os("CXX").map(PathBuf::from));
+        let profile_flags = self.profile.map(|_| self.get_profile_flags());
+        // without an explicit compiler or profile, the cc crate picks the same
+        // compiler and optimization flags as cargo
+        #[cfg(feature = "cc")]
+        let (path, profile_flags) = match (path, profile_flags) {
+            (Some(path), Some(flags)) => (Some(path), Some(flags)),
+            (path, flags) => match self.get_cc_tool() {
+                Some(tool) => (
+                    path.or_else(|| Some(tool.path().to_path_buf())),
+                    flags.or_else(|| {
+                        Some(
+                            tool.args()
+                                .iter()
+                                .map(|a| a.to_string_lossy().into_owned())
+                                .collect(),
+                        )
+                    }),
+                ),
+                None => (path, flags),
+            },
+        };
+        Compiler {
+            path: path.unwrap_or_else(|| PathBuf::from("g++")),
+            profile_flags: profile_flags.unwrap_or_else(|| self.get_profile_flags()),
+        }
+    }
+
+    fn get_profile(&self) -> Profile {
+        if let Some(p) = self.profile {
+            return p;
+        }
+        // cargo sets OPT_LEVEL when running build scripts
+        match env::var("OPT_LEVEL") {
+            Ok(ref level) if level != "0" => Profile::Release,
+            _ => Profile::Debug,
+        }
+    }
+
+    fn get_profile_flags(&self) -> Vec<String> {
+        match self.get_profile() {
+            Profile::Debug => vec!["-O0".to_string(), "-g".to_string()],
+            Profile::Release => vec!["-O2".to_string()],
         }
     }
 
+    // templates in the user handlebars dir override the embedded ones,
+    // other inputs are read from the filesystem
+    fn get_template(&self, input: &str) -> std::io::Result<String> {
+        if let Some(dir) = &self.handlebars_dir {
+            let file = dir.join(input);
+            if file.exists() {
+                return fs::read_to_string(file);
+            }
+        }
+        match TEMPLATES.iter().find(|(name, _)| *name == input) {
+            Some((_, source)) => Ok(source.to_string()),
+            None => fs::read_to_string(input),
+        }
+    }
+
+    // template files read from the filesystem, embedded ones are not listed
+    fn get_template_files(&self) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        if let Some(dir) = &self.handlebars_dir {
+            files.extend(list_files(dir));
+        }
+        for (input, _) in self.templates.iter() {
+            if TEMPLATES.iter().all(|(name, _)| name != input) {
+                files.push(PathBuf::from(input));
+            }
+        }
+        files
+    }
+
     fn get_bin(&self) -> PathBuf {
         match &self.bin {
             Some(b) => b.to_path_buf(),
@@ -112,27 +504,206 @@ impl Build {
         }
     }
 
+    fn get_verilator_root(&self) -> PathBuf {
+        match &self.verilator_root {
+            Some(d) => d.to_path_buf(),
+            None => panic!("verilator root not defined"),
+        }
+    }
+
+    fn get_verilator_include_dir(&self) -> PathBuf {
+        self.get_verilator_root().join("include")
+    }
+
+    // the binary is taken from the setters, then VERILATOR, VERILATOR_ROOT,
+    // the copy built by build.rs and finally PATH, the root defaults to the
+    // one the binary was built with
+    fn find_verilator(&mut self) -> &mut Build {
+        let root = self
+            .verilator_root
+            .clone()
+            .or_else(|| env::var_os("VERILATOR_ROOT").map(PathBuf::from));
+        let bundled = get_lastlayer_root_dir().join("verilator/build/bin/verilator");
+        let bin = match (&self.bin, env::var_os("VERILATOR"), &root) {
+            (Some(b), _, _) => b.to_path_buf(),
+            (None, Some(b), _) => PathBuf::from(b),
+            (None, None, Some(r)) => r.join("bin/verilator"),
+            (None, None, None) if bundled.exists() => bundled,
+            (None, None, None) => PathBuf::from("verilator"),
+        };
+        self.bin = Some(bin);
+        self.verilator_root = match root {
+            Some(r) => Some(r),
+            None => {
+                let output = Command::new(self.get_bin())
+                    .arg("--getenv")
+                    .arg("VERILATOR_ROOT")
+                    .output()
+                    .expect("failed to execute verilator");
+                let r = String::from_utf8_lossy(&output.stdout).trim().to_string();
+                Some(PathBuf::from(r))
+            }
+        };
+        self.check_verilator_version()
+            .unwrap_or_else(|e| panic!("{}", e));
+        self
+    }
+
+    fn check_verilator_version(&self) -> Result<(), String> {
+        let version = self.get_verilator_version();
+        let v = match parse_verilator_version(&version) {
+            Some(v) => v,
+            None => {
+                return Err(format!(
+                    "failed to get Verilator version from {:?}",
+                    self.get_bin()
+                ))
+            }
+        };
+        if v < MIN_VERILATOR_VERSION {
+            return Err(format!(
+                "{} is too old, at least Verilator {}.{:03} is required",
+                version, MIN_VERILATOR_VERSION.0, MIN_VERILATOR_VERSION.1
+            ));
+        }
+        if v.0 > MAX_VERILATOR_MAJOR {
+            return Err(format!(
+                "{} is not supported, only Verilator {}.x releases are",
+                version, MAX_VERILATOR_MAJOR
+            ));
+        }
+        if v < FORCE_VERILATOR_VERSION && !self.force.is_empty() {
+            return Err(format!(
+                "{} does not support force and release, forceable signals need Verilator {}.{:03} or newer",
+                version, FORCE_VERILATOR_VERSION.0, FORCE_VERILATOR_VERSION.1
+            ));
+        }
+        if !self.get_verilator_include_dir().join("verilated.cpp").is_file() {
+            return Err(format!(
+                "Verilator include dir {:?} not found",
+                self.get_verilator_include_dir()
+            ));
+        }
+        Ok(())
+    }
+
     fn render(&self, input: &str, output: &str) -> Result<(), Box<dyn Error>> {
         let reg = Handlebars::new();
-        let handle = VirtualHandle {
+        let mut handle = VirtualHandle {
             vtop: self.get_virtual_top_name(),
             top: self.get_top_name(),
             clock: self.get_clock(),
             reset: self.get_reset(),
             vcd_file: self.get_vcd_file(),
+            params: self.verilog_params.clone(),
+            reset_cycles: 0,
+            run_cycles: 0,
+            reg: self.reg.clone(),
+            mem: self.mem.clone(),
+            force: self.force.clone(),
+            probe: self.probe.clone(),
+            systemverilog: self.systemverilog,
+            reg_wide: self.reg.iter().map(|r| Wide::new(r.hid, r.width)).collect(),
+            mem_wide: self.mem.iter().map(|m| Wide::new(m.hid, m.width)).collect(),
+            force_wide: self.force.iter().map(|f| Wide::new(f.hid, f.width)).collect(),
+            probe_wide: self.probe.iter().map(|p| Wide::new(p.hid, p.width)).collect(),
+            vars: self.template_vars.clone(),
         };
-        let template_path = self.get_handlebars_dir().join(input);
+        if let Output::Executable { reset, run } = self.output {
+            handle.reset_cycles = reset;
+            handle.run_cycles = run;
+        }
+        let template = self.get_template(input)?;
         let output_path = self.get_out_dir().join(output);
-        let mut template_file = File::open(template_path)?;
         let mut output_file = File::create(output_path)?;
-        reg.render_template_source_to_write(&mut template_file, &handle, &mut output_file)?;
+        reg.render_template_to_write(&template, &handle, &mut output_file)?;
         Ok(())
     }
 
+    fn get_fingerprint_file(&self) -> PathBuf {
+        self.get_out_dir().join("fingerprint")
+    }
+
+    fn get_verilator_version(&self) -> String {
+        let output = Command::new(self.get_bin())
+            .arg("--version")
+            .output()
+            .expect("failed to execute verilator");
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    // hash every input of compile_verilog and compile_cxx, generated files
+    // are hashed by content so changes in templates or AWIG are detected too.
+    // Files in out_dir are skipped when listing dirs, they are rewritten by
+    // every build, e.g. when the include dir is the project root
+    fn fingerprint(&self, name: &str, compiler: &Compiler) -> String {
+        let mut hasher = StableHasher::new();
+        let mut files: Vec<PathBuf> = Vec::new();
+        files.extend(self.verilog_files.iter().cloned());
+        files.extend(self.cc_files.iter().cloned());
+        files.extend(self.verilog_lib_files.iter().cloned());
+        let out_dir = self.get_out_dir();
+        let dirs = self
+            .verilog_include_dirs
+            .iter()
+            .chain(self.verilog_lib_dirs.iter())
+            .chain(self.cc_include_dirs.iter());
+        for dir in dirs {
+            files.extend(list_files(dir).into_iter().filter(|f| !f.starts_with(&out_dir)));
+        }
+        files.extend(self.get_template_files());
+        for file in files.iter() {
+            hasher.path(file).field(fs::read(file).unwrap_or_default());
+        }
+        for (template, source) in TEMPLATES.iter() {
+            hasher.field(template).field(source);
+        }
+        let defines = self.verilog_defines.iter().map(|(name, value)| match value {
+            Some(v) => format!("{}={}", name, v),
+            None => name.to_string(),
+        });
+        hasher
+            .field(HEADER)
+            .field(name)
+            .field(self.get_verilator_version())
+            .path(&self.get_verilator_include_dir())
+            .field(self.get_virtual_top_name())
+            .list(self.verilog_warnings.iter())
+            .paths(self.verilog_include_dirs.iter())
+            .paths(self.verilog_lib_dirs.iter())
+            .list(defines)
+            .list(self.verilator_args.iter())
+            .list(self.cc_flags.iter())
+            .paths(self.cc_include_dirs.iter())
+            .paths(self.cc_link_dirs.iter())
+            .list(self.cc_link_libs.iter())
+            .field(format!("{} {:?}", self.has_trace(), self.trace_format))
+            .field(format!("{} {}", self.savable, self.threads.unwrap_or(0)))
+            .field(format!("{:?}", self.output))
+            .path(&compiler.path)
+            .list(compiler.profile_flags.iter());
+        hasher.finish()
+    }
+
+    fn get_output_file(&self, name: &str) -> PathBuf {
+        let file = match self.output {
+            Output::Shared => format!("lib{}.so", name),
+            Output::Static => format!("lib{}.a", name),
+            Output::Executable { .. } => name.to_string(),
+        };
+        self.get_out_dir().join(file)
+    }
+
+    fn is_up_to_date(&self, name: &str, fingerprint: &str) -> bool {
+        let output = self.get_output_file(name);
+        match fs::read_to_string(self.get_fingerprint_file()) {
+            Ok(f) => !self.force_rebuild && output.exists() && f.trim() == fingerprint,
+            Err(_) => false,
+        }
+    }
+
     fn create_out_dir(&self) {
-        let mut cmd = Command::new("mkdir");
-        cmd.arg("-p").arg(self.get_out_dir());
-        run_cmd(&mut cmd);
+        create_dir(&self.get_out_dir()).unwrap_or_else(|e| panic!("{}", e));
     }
 
     fn create_virtual_verilog_top(&mut self) -> &mut Build {
@@ -155,9 +726,86 @@ impl Build {
         self
     }
 
+    fn render_templates(&self) {
+        for (input, output) in self.templates.iter() {
+            self.render(input, output)
+                .unwrap_or_else(|e| panic!("failed to render template {}: {}", input, e));
+        }
+    }
+
+    fn create_main(&mut self) -> &mut Build {
+        let cc_name = format!("{}_main.cc", self.tool_name);
+        let hbs_name = format!("{}.hbs", &cc_name);
+        let cc_file = self.get_out_dir().join(&cc_name);
+        self.render(&hbs_name, &cc_name)
+            .expect("failed to render main");
+        self.cc_file(&cc_file);
+        self
+    }
+
+    fn create_rust_bindings(&self) {
+        let rs_name = format!("{}.rs", self.tool_name);
+        let hbs_name = format!("{}.hbs", &rs_name);
+        self.render(&hbs_name, &rs_name)
+            .expect("failed to render rust bindings");
+    }
+
+    // only user inputs are listed, generated files in out_dir are rewritten
+    // on every build and would make cargo rerun the build script forever
+    // cargo scans directories recursively, so they are passed as they are
+    // and files added to them later are picked up too. Once a file is listed
+    // cargo ignores the environment, so the variables compile reads are too
+    fn get_cargo_rerun(&self) -> Vec<String> {
+        let templates = self
+            .templates
+            .iter()
+            .map(|(input, _)| PathBuf::from(input))
+            .filter(|input| TEMPLATES.iter().all(|(name, _)| Path::new(name) != input));
+        self.verilog_files
+            .iter()
+            .chain(self.verilog_lib_files.iter())
+            .chain(self.cc_files.iter())
+            .chain(self.verilog_include_dirs.iter())
+            .chain(self.verilog_lib_dirs.iter())
+            .chain(self.cc_include_dirs.iter())
+            .chain(self.handlebars_dir.iter())
+            .cloned()
+            .chain(templates)
+            .map(|path| format!("cargo:rerun-if-changed={}", path.display()))
+            .chain(
+                ENV_VARS
+                    .iter()
+                    .map(|var| format!("cargo:rerun-if-env-changed={}", var)),
+            )
+            .collect()
+    }
+
+    fn emit_cargo_rerun(&self) {
+        for line in self.get_cargo_rerun() {
+            println!("{}", line);
+        }
+    }
+
+    fn get_dispatch(&self) -> awig::Dispatch {
+        if self.dense_hids {
+            awig::Dispatch::Dense
+        } else {
+            awig::Dispatch::Case
+        }
+    }
+
+    fn get_language(&self) -> awig::Language {
+        if self.systemverilog {
+            awig::Language::SystemVerilog
+        } else {
+            awig::Language::Verilog
+        }
+    }
+
     fn compile_awig(&mut self) -> &mut Build {
         let name = format!("{}_dpi", self.get_virtual_top_name());
-        let filename = format!("{}.v", &name);
+        let ext = if self.systemverilog { "sv" } else { "v" };
+        let filename = format!("{}.{}", &name, ext);
         let file = self.get_out_dir().join(&filename);
         awig::compile(
             &file,
@@ -165,10 +813,16 @@ impl Build {
             &name,
             "dpi_reg",
             "dpi_mem",
+            "dpi_sig",
+            "dpi_probe",
             &self.reg,
             &self.mem,
+            &self.force,
+            &self.probe,
+            self.get_dispatch(),
+            self.get_language(),
         )
-        .expect("AWIG failed");
+        .unwrap_or_else(|e| panic!("AWIG failed: {}", e));
         self.verilog_file(file);
         self
     }
@@ -189,16 +843,138 @@ impl Build {
             let dir_name = dir.to_str().unwrap().to_owned();
             cmd.arg(format!("-I{}", dir_name));  // It seems that Verilator does not support space between the path and -I
         }
+        for dir in self.verilog_lib_dirs.iter() {
+            cmd.arg("-y").arg(dir);
+        }
+        for file in self.verilog_lib_files.iter() {
+            cmd.arg("-v").arg(file);
+        }
+        for (name, value) in self.verilog_defines.iter() {
+            match value {
+                Some(v) => cmd.arg(format!("+define+{}={}", name, v)),
+                None => cmd.arg(format!("+define+{}", name)),
+            };
+        }
         for file in self.verilog_files.iter() {
             cmd.arg(file);
         }
         for warn in &self.verilog_warnings {
             cmd.arg(format!("-Wno-{}", warn));
         }
-        if self.vcd_file != None {
-          cmd.arg("--trace");
+        if self.has_trace() {
+          match self.trace_format {
+            TraceFormat::Vcd => cmd.arg("--trace"),
+            TraceFormat::Fst => cmd.arg("--trace-fst"),
+          };
+        }
+        if self.savable {
+          cmd.arg("--savable");
+        }
+        if let Some(n) = self.threads {
+          cmd.arg("--threads").arg(n.to_string());
+        }
+        for arg in self.verilator_args.iter() {
+          cmd.arg(arg);
+        }
+        self.run_step(&mut cmd);
+    }
+
+    fn get_log_file(&self) -> PathBuf {
+        self.get_out_dir().join("build.log")
+    }
+
+    fn open_log(&self) {
+        let path = self.get_log_file();
+        let file = File::create(&path)
+            .unwrap_or_else(|e| panic!("{}", FsError::new("create", &path, e)));
+        *self.log.lock().unwrap() = Some(file);
+    }
+
+    fn close_log(&self) {
+        *self.log.lock().unwrap() = None;
+    }
+
+    fn log(&self, entry: &str) {
+        if let Some(file) = self.log.lock().unwrap().as_mut() {
+            file.write_all(entry.as_bytes())
+                .unwrap_or_else(|e| panic!("{}", FsError::new("write", &self.get_log_file(), e)));
+        }
+    }
+
+    // run a Verilator or C++ compiler step, the command line and its output
+    // are written to build.log and forwarded to stdout and stderr
+    fn run_step(&self, cmd: &mut Command) {
+        let output = cmd
+            .output()
+            .unwrap_or_else(|e| panic!("failed to execute {:?}: {}", cmd, e));
+        let mut entry = format!("$ {:?}\n", cmd);
+        entry.push_str(&String::from_utf8_lossy(&output.stdout));
+        entry.push_str(&String::from_utf8_lossy(&output.stderr));
+        if !output.status.success() {
+            entry.push_str(&format!("{}\n", output.status));
+        }
+        self.log(&entry);
+        let _ = io::stdout().write_all(&output.stdout);
+        let _ = io::stderr().write_all(&output.stderr);
+        if !output.status.success() {
+            panic!(
+                "command did not execute successfully, see {}",
+                self.get_log_file().display()
+            );
+        }
+    }
+
+    fn get_cxx_version(&self, compiler: &Compiler) -> String {
+        match Command::new(&compiler.path).arg("--version").output() {
+            Ok(output) => String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            Err(_) => String::new(),
         }
-        run_cmd(&mut cmd);
+    }
+
+    fn write_artifacts(&self, name: &str, compiler: &Compiler) {
+        let out_dir = self.get_out_dir();
+        let verilog = self
+            .verilog_files
+            .iter()
+            .filter(|f| {
+                f.starts_with(&out_dir) && f.extension().is_some_and(|e| e == "v" || e == "sv")
+            })
+            .cloned()
+            .collect();
+        let bindings = if self.rust_bindings {
+            Some(out_dir.join(format!("{}.rs", self.tool_name)))
+        } else {
+            None
+        };
+        let artifacts = Artifacts {
+            name: name.to_string(),
+            output: format!("{:?}", self.output),
+            library: self.get_output_file(name),
+            header: out_dir.join("lastlayer.h"),
+            bindings,
+            verilog,
+            sources: self
+                .verilator_cc_files
+                .iter()
+                .chain(self.cc_files.iter())
+                .cloned()
+                .collect(),
+            registers: self.reg.clone(),
+            memories: self.mem.clone(),
+            forces: self.force.clone(),
+            probes: self.probe.clone(),
+            verilator_version: self.get_verilator_version(),
+            cxx: compiler.path.clone(),
+            cxx_version: self.get_cxx_version(compiler),
+        };
+        let json = serde_json::to_string_pretty(&artifacts)
+            .expect("failed to serialize artifacts");
+        write_file(&out_dir.join("artifacts.json"), &json)
+            .unwrap_or_else(|e| panic!("{}", e));
     }
 
     fn default_verilog_warning(&mut self) -> &mut Build {
@@ -212,47 +988,173 @@ impl Build {
     fn get_verilated_cxx_files(&self) -> Vec<PathBuf> {
         let mut cxx_files = Vec::new();
         let out_dir = self.get_out_dir();
-        for entry in glob(&out_dir.join(format!("V{}*.cpp", self.get_virtual_top_name())).to_str().unwrap()).unwrap() {
-          if let Ok(path) = entry {
-            cxx_files.push(path)
-          }
+        for path in glob(out_dir.join(format!("V{}*.cpp", self.get_virtual_top_name())).to_str().unwrap()).unwrap().flatten() {
+          cxx_files.push(path)
         }
         cxx_files
     }
 
+    fn verilator_cc_file<P: AsRef<Path>>(&mut self, file: P) -> &mut Build {
+        self.verilator_cc_files.push(file.as_ref().to_path_buf());
+        self
+    }
+
+    // Verilator runtime sources only depend on flags, so their objects are
+    // shared between designs through the cache dir
     fn default_cc_files(&mut self) -> &mut Build {
-        let include_dir = get_lastlayer_root_dir().join("verilator/build/share/verilator/include");
+        let include_dir = self.get_verilator_include_dir();
         let out_dir = self.get_out_dir();
-        self.cc_file(&include_dir.join("verilated.cpp"));
-        self.cc_file(&include_dir.join("verilated_dpi.cpp"));
-        self.cc_file(&out_dir.join(format!("{}.cc", self.tool_name)));
+        self.verilator_cc_file(include_dir.join("verilated.cpp"));
+        self.verilator_cc_file(include_dir.join("verilated_dpi.cpp"));
+        self.cc_file(out_dir.join(format!("{}.cc", self.tool_name)));
         for file in self.get_verilated_cxx_files().iter() {
-          self.cc_file(&file);
+          self.cc_file(file);
+        }
+        if self.has_trace() {
+          match self.trace_format {
+            TraceFormat::Vcd => self.verilator_cc_file(include_dir.join("verilated_vcd_c.cpp")),
+            TraceFormat::Fst => self.verilator_cc_file(include_dir.join("verilated_fst_c.cpp")),
+          };
+        }
+        if self.savable {
+          self.verilator_cc_file(include_dir.join("verilated_save.cpp"));
         }
-        if self.vcd_file != None {
-          self.cc_file(&include_dir.join("verilated_vcd_c.cpp"));
+        if self.threads.is_some() {
+          self.verilator_cc_file(include_dir.join("verilated_threads.cpp"));
         }
         self
     }
 
     fn default_include_dirs(&mut self) -> &mut Build {
-        let include_dir = get_lastlayer_root_dir().join("include/lastlayer");
-        let verilator_dir = get_lastlayer_root_dir().join("verilator/build/share/verilator/include");
+        let verilator_dir = self.get_verilator_include_dir();
         self.cc_include_dir(self.get_out_dir());
         self.cc_include_dir(&verilator_dir);
-        self.cc_include_dir(&verilator_dir.join("vltstd"));
-        self.cc_include_dir(&include_dir);
+        self.cc_include_dir(verilator_dir.join("vltstd"));
         self
     }
 
-    fn compile_cxx(&mut self, name: &str) {
-        let out_dir = self.get_out_dir();
-        let mut cmd = Command::new("g++");
-        cmd.arg("-shared")
-            .arg("-faligned-new")
-            .arg("-fPIC");
+    fn get_base_flags(&self) -> Vec<String> {
+        let mut flags = vec!["-faligned-new".to_string(), "-fPIC".to_string()];
+        // the threaded runtime needs C++11, which is the default standard of
+        // every supported compiler, so -std is left to the user flags
+        if self.threads.is_some() {
+            flags.push("-pthread".to_string());
+        }
+        flags
+    }
+
+    fn get_cxx_flags(&self, compiler: &Compiler) -> Vec<String> {
+        let mut flags = self.get_base_flags();
         for dir in self.cc_include_dirs.iter() {
-            cmd.arg("-I").arg(dir);
+            flags.push(format!("-I{}", dir.display()));
+        }
+        // user flags come last so they can override the profile
+        flags.extend(compiler.profile_flags.iter().cloned());
+        flags.extend(self.cc_flags.iter().cloned());
+        flags.extend(self.get_define_flags());
+        flags
+    }
+
+    // the Verilator runtime only sees its own headers, so its objects do not
+    // depend on out_dir or the user include dirs and can be shared between
+    // designs. User flags are kept, they can change the ABI, e.g. -m32
+    fn get_runtime_flags(&self, compiler: &Compiler) -> Vec<String> {
+        let include_dir = self.get_verilator_include_dir();
+        let mut flags = self.get_base_flags();
+        flags.push(format!("-I{}", include_dir.display()));
+        flags.push(format!("-I{}", include_dir.join("vltstd").display()));
+        flags.extend(compiler.profile_flags.iter().cloned());
+        flags.extend(self.cc_flags.iter().cloned());
+        flags.extend(self.get_define_flags());
+        flags
+    }
+
+    // everything a cached runtime object depends on besides its source
+    fn get_runtime_key(&self, compiler: &Compiler, flags: &[String]) -> String {
+        StableHasher::new()
+            .path(&compiler.path)
+            .list(flags.iter())
+            .field(self.get_verilator_version())
+            .finish()
+    }
+
+    fn get_define_flags(&self) -> Vec<String> {
+        let mut flags = Vec::new();
+        if self.has_trace() {
+            flags.push("-DLASTLAYER_TRACE".to_string());
+            if self.trace_format == TraceFormat::Fst {
+                flags.push("-DLASTLAYER_FST".to_string());
+            }
+        }
+        if self.savable {
+            flags.push("-DLASTLAYER_SAVABLE".to_string());
+        }
+        if self.threads.is_some() {
+            flags.push("-DVL_THREADED".to_string());
+        }
+        // $finish, $stop and fatal errors are reported by lastlayer.cc.hbs
+        flags.push("-DVL_USER_FINISH".to_string());
+        flags.push("-DVL_USER_STOP".to_string());
+        flags.push("-DVL_USER_STOP_MAYBE".to_string());
+        flags.push("-DVL_USER_FATAL".to_string());
+        flags
+    }
+
+    // file name is made unique with a hash because sources from different
+    // directories can share a name, cached objects also hash their key and content
+    fn get_object_file(&self, dir: &Path, file: &Path, key: Option<&str>) -> PathBuf {
+        let mut hasher = StableHasher::new();
+        hasher.path(file);
+        if let Some(key) = key {
+            hasher.field(key).field(fs::read(file).unwrap_or_default());
+        }
+        let stem = file.file_stem().unwrap().to_string_lossy();
+        dir.join(format!("{}-{}.o", stem, hasher.finish()))
+    }
+
+    fn compile_object(&self, compiler: &Compiler, file: &Path, obj: &Path, flags: &[String]) {
+        // write to a temporary file first, so concurrent builds sharing the
+        // cache never see a partial object, the counter keeps the name unique
+        // between threads of one process, e.g. designs built by parallel tests
+        static NEXT_TMP: AtomicUsize = AtomicUsize::new(0);
+        let n = NEXT_TMP.fetch_add(1, Ordering::SeqCst);
+        let tmp = obj.with_extension(format!("o.{}.{}.tmp", std::process::id(), n));
+        let mut cmd = Command::new(&compiler.path);
+        cmd.arg("-c").args(flags).arg(file).arg("-o").arg(&tmp);
+        self.run_step(&mut cmd);
+        rename(&tmp, obj).unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    fn compile_objects(&self, compiler: &Compiler, objects: Vec<(PathBuf, PathBuf, &[String])>) {
+        let queue = Mutex::new(objects.into_iter());
+        thread::scope(|s| {
+            for _ in 0..self.get_jobs() {
+                s.spawn(|| loop {
+                    let next = queue.lock().unwrap().next();
+                    match next {
+                        Some((file, obj, flags)) => {
+                            self.compile_object(compiler, &file, &obj, flags)
+                        }
+                        None => break,
+                    }
+                });
+            }
+        });
+    }
+
+    fn link_cxx(&self, compiler: &Compiler, name: &str, objects: &[PathBuf]) {
+        let mut cmd = Command::new(&compiler.path);
+        if self.output == Output::Shared {
+            cmd.arg("-shared");
+        }
+        if self.threads.is_some() {
+            cmd.arg("-pthread");
+        }
+        for flag in self.cc_flags.iter() {
+            cmd.arg(flag);
+        }
+        for obj in objects.iter() {
+            cmd.arg(obj);
         }
         for dir in self.cc_link_dirs.iter() {
             cmd.arg("-L").arg(dir);
@@ -260,32 +1162,123 @@ impl Build {
         for lib in self.cc_link_libs.iter() {
             cmd.arg(format!("-l{}", lib));
         }
-        for flag in self.cc_flags.iter() {
-            cmd.arg(flag);
+        if self.has_trace() && self.trace_format == TraceFormat::Fst {
+            // verilated_fst_c.cpp compresses with zlib
+            cmd.arg("-lz");
+        }
+        cmd.arg("-o").arg(self.get_output_file(name));
+        self.run_step(&mut cmd);
+    }
+
+    fn archive_cxx(&self, name: &str, objects: &[PathBuf]) {
+        let lib = self.get_output_file(name);
+        // ar only adds to an existing archive, stale objects must go
+        let _ = fs::remove_file(&lib);
+        let mut cmd = Command::new(env::var_os("AR").unwrap_or_else(|| "ar".into()));
+        cmd.arg("crs").arg(&lib);
+        for obj in objects.iter() {
+            cmd.arg(obj);
+        }
+        self.run_step(&mut cmd);
+    }
+
+    // the C++ standard library the compiler links by default, CXXSTDLIB
+    // overrides it like for the cc crate and an empty value links none
+    fn get_cxx_stdlib(&self, compiler: &Compiler) -> Option<String> {
+        if let Ok(lib) = env::var("CXXSTDLIB") {
+            return Some(lib).filter(|l| !l.is_empty());
+        }
+        // libc++ defines _LIBCPP_VERSION in every header, libstdc++ does not
+        let probe = || -> io::Result<bool> {
+            let mut child = Command::new(&compiler.path)
+                .args(["-x", "c++", "-E", "-dM", "-"])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()?;
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(b"#include <cstddef>\n")?;
+            }
+            let output = child.wait_with_output()?;
+            Ok(String::from_utf8_lossy(&output.stdout).contains("_LIBCPP_VERSION"))
+        };
+        match probe() {
+            Ok(true) => Some("c++".to_string()),
+            _ => Some("stdc++".to_string()),
+        }
+    }
+
+    // a static library does not record its dependencies, so cargo is told
+    // about them along with the library itself
+    fn get_cargo_link(&self, name: &str, stdlib: Option<&str>) -> Vec<String> {
+        let mut lines = vec![
+            format!("cargo:rustc-link-search=native={}", self.get_out_dir().display()),
+            format!("cargo:rustc-link-lib=static={}", name),
+        ];
+        for dir in self.cc_link_dirs.iter() {
+            lines.push(format!("cargo:rustc-link-search=native={}", dir.display()));
+        }
+        for lib in self.cc_link_libs.iter() {
+            lines.push(format!("cargo:rustc-link-lib={}", lib));
+        }
+        if self.has_trace() && self.trace_format == TraceFormat::Fst {
+            lines.push("cargo:rustc-link-lib=z".to_string());
+        }
+        if self.threads.is_some() {
+            lines.push("cargo:rustc-link-lib=pthread".to_string());
+        }
+        if let Some(lib) = stdlib {
+            lines.push(format!("cargo:rustc-link-lib={}", lib));
+        }
+        lines
+    }
+
+    fn emit_cargo_link(&self, name: &str, compiler: &Compiler) {
+        let stdlib = self.get_cxx_stdlib(compiler);
+        for line in self.get_cargo_link(name, stdlib.as_deref()) {
+            println!("{}", line);
+        }
+    }
+
+    fn compile_cxx(&mut self, name: &str, compiler: &Compiler) {
+        let flags = self.get_cxx_flags(compiler);
+        let runtime_flags = self.get_runtime_flags(compiler);
+        let runtime_key = self.get_runtime_key(compiler, &runtime_flags);
+        let obj_dir = self.get_out_dir().join("obj");
+        let cache_dir = self.get_cache_dir();
+        create_dir(&obj_dir).unwrap_or_else(|e| panic!("{}", e));
+        create_dir(&cache_dir).unwrap_or_else(|e| panic!("{}", e));
+        let mut objects = Vec::new();
+        let mut pending = Vec::new();
+        for file in self.verilator_cc_files.iter() {
+            let obj = self.get_object_file(&cache_dir, file, Some(&runtime_key));
+            if !obj.exists() {
+                pending.push((file.to_path_buf(), obj.clone(), runtime_flags.as_slice()));
+            }
+            objects.push(obj);
         }
         for file in self.cc_files.iter() {
-            cmd.arg(file);
+            let obj = self.get_object_file(&obj_dir, file, None);
+            pending.push((file.to_path_buf(), obj.clone(), flags.as_slice()));
+            objects.push(obj);
         }
-        if self.vcd_file != None {
-            cmd.arg(format!("-DLASTLAYER_VCD"));
+        self.compile_objects(compiler, pending);
+        match self.output {
+            Output::Static => self.archive_cxx(name, &objects),
+            _ => self.link_cxx(compiler, name, &objects),
         }
-        cmd.arg("-o").arg(&out_dir.join(format!("lib{}.so", name)));
-        run_cmd(&mut cmd);
     }
 
-    fn copy_header(&self) {
-        let mut cmd = Command::new("cp");
-        cmd.arg(get_lastlayer_root_dir().join("include/lastlayer/lastlayer.h"))
-            .arg(self.get_out_dir());
-        run_cmd(&mut cmd);
+    // lastlayer.cc includes the header from out_dir
+    fn write_header(&self) {
+        write_file(&self.get_out_dir().join("lastlayer.h"), HEADER)
+            .unwrap_or_else(|e| panic!("{}", e));
     }
 
+    // out_dir/verilator points to the Verilator headers used by the build
     fn create_link_to_verilator_include(&self) {
-        let mut cmd = Command::new("ln");
-        cmd.arg("-sf")
-            .arg(get_lastlayer_root_dir().join("verilator/build/share/verilator/include"))
-            .arg(self.get_out_dir().join("verilator"));
-        run_cmd(&mut cmd);
+        symlink_dir(&self.get_verilator_include_dir(), &self.get_out_dir().join("verilator"))
+            .unwrap_or_else(|e| panic!("{}", e));
     }
 
     pub fn new() -> Build {
@@ -298,34 +1291,82 @@ impl Build {
             verilog_warnings: Vec::new(),
             verilog_files: Vec::new(),
             verilog_include_dirs: Vec::new(),
+            verilog_lib_dirs: Vec::new(),
+            verilog_lib_files: Vec::new(),
+            verilog_defines: Vec::new(),
+            verilog_params: Vec::new(),
+            verilator_args: Vec::new(),
             cc_flags: Vec::new(),
             cc_include_dirs: Vec::new(),
             cc_link_dirs: Vec::new(),
             cc_link_libs: Vec::new(),
             cc_files: Vec::new(),
+            verilator_cc_files: Vec::new(),
+            cache_dir: None,
+            jobs: None,
+            threads: None,
+            cxx: None,
+            profile: None,
+            output: Output::Shared,
             out_dir: None,
             vcd_file: None,
-            handlebars_dir: Some(get_lastlayer_root_dir().join("src/handlebars")),
-            bin: Some(get_lastlayer_root_dir().join("verilator/build/bin/verilator")),
+            trace: false,
+            trace_format: TraceFormat::Vcd,
+            savable: false,
+            dense_hids: false,
+            systemverilog: false,
+            force_rebuild: false,
+            verilator_include_link: true,
+            rust_bindings: false,
+            cargo_metadata: false,
+            log: Mutex::new(None),
+            handlebars_dir: None,
+            templates: Vec::new(),
+            template_vars: BTreeMap::new(),
+            bin: None,
+            verilator_root: None,
             reg: Vec::new(),
             mem: Vec::new(),
+            force: Vec::new(),
+            probe: Vec::new(),
         }
     }
 
     pub fn add_register(&mut self, hid: u32, path: &str, width: u32) -> &mut Build {
         self.reg.push(Register {
-            hid: hid,
+            hid,
             path: path.to_string(),
-            width: width,
+            width,
         });
         self
     }
 
     pub fn add_memory(&mut self, hid: u32, path: &str, width: u32) -> &mut Build {
         self.mem.push(Memory {
-            hid: hid,
+            hid,
             path: path.to_string(),
-            width: width,
+            width,
+        });
+        self
+    }
+
+    // probes are read-only, so path can be any wire or module output
+    pub fn add_probe(&mut self, hid: u32, path: &str, width: u32) -> &mut Build {
+        self.probe.push(Probe {
+            hid,
+            path: path.to_string(),
+            width,
+        });
+        self
+    }
+
+    // path can be any net or variable, e.g. a wire driven by assign, it is
+    // overridden with force and release, which needs Verilator 4.220 or newer
+    pub fn add_force(&mut self, hid: u32, path: &str, width: u32) -> &mut Build {
+        self.force.push(Force {
+            hid,
+            path: path.to_string(),
+            width,
         });
         self
     }
@@ -356,30 +1397,132 @@ impl Build {
         self
     }
 
+    // trace into name from alloc, implies trace(true), the file is written
+    // in the format set with trace_format
     pub fn vcd_file(&mut self, name: &str) -> &mut Build {
         self.vcd_file = Some(name.to_string());
         self
     }
 
+    // build with tracing support, controlled at runtime with LastLayerTraceOpen
+    pub fn trace(&mut self, enable: bool) -> &mut Build {
+        self.trace = enable;
+        self
+    }
+
+    pub fn trace_format(&mut self, format: TraceFormat) -> &mut Build {
+        self.trace_format = format;
+        self
+    }
+
+    // build with support for LastLayerSave and LastLayerRestore
+    pub fn savable(&mut self, enable: bool) -> &mut Build {
+        self.savable = enable;
+        self
+    }
+
+    // hids of each kind are 0 to n-1, so DPI dispatch only decodes the bits needed
+    pub fn dense_hids(&mut self, enable: bool) -> &mut Build {
+        self.dense_hids = enable;
+        self
+    }
+
+    // generate the DPI module as SystemVerilog, which also enables the
+    // LastLayer*Wide functions for values wider than 32 bits
+    pub fn systemverilog(&mut self, enable: bool) -> &mut Build {
+        self.systemverilog = enable;
+        self
+    }
+
+    // run Verilator and the C++ compiler even if inputs did not change
+    pub fn force_rebuild(&mut self, enable: bool) -> &mut Build {
+        self.force_rebuild = enable;
+        self
+    }
+
+    // link out_dir/verilator to the Verilator include dir, enabled by default
+    pub fn verilator_include_link(&mut self, enable: bool) -> &mut Build {
+        self.verilator_include_link = enable;
+        self
+    }
+
+    // number of C++ files compiled in parallel
+    pub fn jobs(&mut self, n: usize) -> &mut Build {
+        self.jobs = Some(n);
+        self
+    }
+
+    // evaluate the model with n threads, the C++ runtime is built with VL_THREADED
+    pub fn threads(&mut self, n: u32) -> &mut Build {
+        self.threads = Some(n);
+        self
+    }
+
+    // C++ compiler, defaults to CXX or g++
+    pub fn cxx<P: AsRef<Path>>(&mut self, cxx: P) -> &mut Build {
+        self.cxx = Some(cxx.as_ref().to_path_buf());
+        self
+    }
+
+    // optimization profile of the C++ model, defaults to the cargo profile
+    pub fn profile(&mut self, profile: Profile) -> &mut Build {
+        self.profile = Some(profile);
+        self
+    }
+
+    pub fn output(&mut self, output: Output) -> &mut Build {
+        self.output = output;
+        self
+    }
+
+    // templates found in dir are used instead of the default ones
+    pub fn handlebars_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Build {
+        self.handlebars_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    // render an extra template into out_dir, input is looked up in the
+    // handlebars dir first and is otherwise a path
+    pub fn template(&mut self, input: &str, output: &str) -> &mut Build {
+        self.templates.push((input.to_string(), output.to_string()));
+        self
+    }
+
+    // available as {{vars.name}} in every template
+    pub fn template_var(&mut self, name: &str, value: &str) -> &mut Build {
+        self.template_vars.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    // objects of the Verilator runtime are cached here and shared by designs,
+    // defaults to $XDG_CACHE_HOME/lastlayer or ~/.cache/lastlayer
+    pub fn cache_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Build {
+        self.cache_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn verilator_bin<P: AsRef<Path>>(&mut self, bin: P) -> &mut Build {
+        self.bin = Some(bin.as_ref().to_path_buf());
+        self
+    }
+
+    // root of a Verilator installation, with bin/verilator and include
+    pub fn verilator_root<P: AsRef<Path>>(&mut self, dir: P) -> &mut Build {
+        self.verilator_root = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
     pub fn cc_flag(&mut self, name: &str) -> &mut Build {
         self.cc_flags.push(name.to_string());
         self
     }
 
     pub fn cc_include_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Build {
-        assert!(
-            dir.as_ref().is_dir(),
-            "include dir does not seems to be a directory"
-        );
         self.cc_include_dirs.push(dir.as_ref().to_path_buf());
         self
     }
 
     pub fn cc_link_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Build {
-        assert!(
-            dir.as_ref().is_dir(),
-            "linking dir does not seems to be a directory"
-        );
         self.cc_link_dirs.push(dir.as_ref().to_path_buf());
         self
     }
@@ -400,25 +1543,532 @@ impl Build {
     }
 
     pub fn verilog_include_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Build {
-        assert!(
-            dir.as_ref().is_dir(),
-            "include dir does not seems to be a directory"
-        );
         self.verilog_include_dirs.push(dir.as_ref().to_path_buf());
         self
     }
 
+    // passed as +define+name or +define+name=value
+    pub fn define(&mut self, name: &str, value: Option<&str>) -> &mut Build {
+        self.verilog_defines
+            .push((name.to_string(), value.map(|v| v.to_string())));
+        self
+    }
+
+    // override a parameter of the top module, value is a Verilog expression
+    pub fn parameter(&mut self, name: &str, value: &str) -> &mut Build {
+        self.verilog_params.push(Parameter {
+            name: name.to_string(),
+            value: value.to_string(),
+        });
+        self
+    }
+
+    // directory searched for modules not found in the Verilog files (-y)
+    pub fn verilog_lib_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Build {
+        self.verilog_lib_dirs.push(dir.as_ref().to_path_buf());
+        self
+    }
+
+    // file searched for modules not found in the Verilog files (-v)
+    pub fn verilog_lib_file<P: AsRef<Path>>(&mut self, file: P) -> &mut Build {
+        self.verilog_lib_files.push(file.as_ref().to_path_buf());
+        self
+    }
+
+    // passed to Verilator as is, e.g. -O3, --x-assign or -f
+    pub fn verilator_arg(&mut self, arg: &str) -> &mut Build {
+        self.verilator_args.push(arg.to_string());
+        self
+    }
+
+    // check the configuration without running any tool, every problem is
+    // reported instead of stopping at the first one
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let mut problems = Vec::new();
+        if self.top_name.is_none() {
+            problems.push("top module not set, see Build::top_module".to_string());
+        }
+        if self.out_dir.is_none() {
+            problems.push("output dir not set, see Build::out_dir".to_string());
+        }
+        if self.verilog_files.is_empty() {
+            problems.push("no Verilog files, see Build::verilog_file".to_string());
+        }
+        if self.threads == Some(0) {
+            problems.push("number of threads must be greater than zero".to_string());
+        }
+        if let Output::Executable { reset, run } = self.output {
+            if reset.max(run) > i32::MAX as u32 {
+                problems.push("reset and run cycles must fit in a C int".to_string());
+            }
+        }
+        for file in self.verilog_files.iter() {
+            check_file(&mut problems, "Verilog file", file);
+        }
+        for file in self.verilog_lib_files.iter() {
+            check_file(&mut problems, "Verilog library file", file);
+        }
+        // C++ files in out_dir can be generated during compile
+        let out_dir = self.out_dir.clone().unwrap_or_default();
+        for file in self.cc_files.iter().filter(|f| !f.starts_with(&out_dir)) {
+            check_file(&mut problems, "C++ file", file);
+        }
+        for dir in self.verilog_include_dirs.iter() {
+            check_dir(&mut problems, "Verilog include dir", dir);
+        }
+        for dir in self.verilog_lib_dirs.iter() {
+            check_dir(&mut problems, "Verilog library dir", dir);
+        }
+        for dir in self.cc_include_dirs.iter() {
+            check_dir(&mut problems, "C++ include dir", dir);
+        }
+        for dir in self.cc_link_dirs.iter() {
+            check_dir(&mut problems, "C++ link dir", dir);
+        }
+        if let Some(dir) = &self.handlebars_dir {
+            check_dir(&mut problems, "handlebars dir", dir);
+        }
+        for (input, _) in self.templates.iter() {
+            if self.get_template(input).is_err() {
+                problems.push(format!("template {} not found", input));
+            }
+        }
+        let dispatch = self.get_dispatch();
+        let reg = self.reg.iter().map(|r| (r.hid, r.path.as_str(), r.width));
+        let mem = self.mem.iter().map(|m| (m.hid, m.path.as_str(), m.width));
+        let force = self.force.iter().map(|f| (f.hid, f.path.as_str(), f.width));
+        let probe = self.probe.iter().map(|p| (p.hid, p.path.as_str(), p.width));
+        let checks = vec![
+            awig::check_declarations("register", dispatch, reg),
+            awig::check_declarations("memory", dispatch, mem),
+            awig::check_declarations("forceable signal", dispatch, force),
+            awig::check_declarations("probe", dispatch, probe),
+        ];
+        for e in checks.into_iter().flatten() {
+            problems.push(e.to_string());
+        }
+        for e in awig::check_function_names(&self.reg, &self.mem, &self.force, &self.probe) {
+            problems.push(e.to_string());
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError { problems })
+        }
+    }
+
+    // compile from a build script, out_dir defaults to OUT_DIR and the design
+    // is linked statically into the crate, the generated bindings are used with
+    // include!(concat!(env!("OUT_DIR"), "/lastlayer.rs"))
+    pub fn compile_for_cargo(&mut self, name: &str) {
+        if self.out_dir.is_none() {
+            let out_dir = env::var_os("OUT_DIR")
+                .expect("OUT_DIR not set, compile_for_cargo must run in a build script");
+            self.out_dir(out_dir);
+        }
+        self.output(Output::Static);
+        self.rust_bindings = true;
+        self.cargo_metadata = true;
+        self.emit_cargo_rerun();
+        self.compile(name);
+    }
+
     pub fn compile(&mut self, name: &str) {
+        if let Err(e) = self.validate() {
+            panic!("{}", e);
+        }
+        self.find_verilator();
         self.create_out_dir();
+        self.open_log();
+        self.write_header();
         self.create_virtual_verilog_top();
         self.create_virtual_cc_top();
+        if let Output::Executable { .. } = self.output {
+            self.create_main();
+        }
         self.compile_awig();
+        self.render_templates();
         self.default_verilog_warning();
-        self.compile_verilog();
+        let compiler = self.get_compiler();
+        let fingerprint = self.fingerprint(name, &compiler);
+        let up_to_date = self.is_up_to_date(name, &fingerprint);
+        if up_to_date {
+            self.log("inputs unchanged, skipping Verilator and C++ compiler\n");
+        } else {
+            // a failed build must not leave a stale fingerprint behind
+            let _ = fs::remove_file(self.get_fingerprint_file());
+            self.compile_verilog();
+        }
         self.default_cc_files();
         self.default_include_dirs();
-        self.compile_cxx(name);
-        self.copy_header();
-        self.create_link_to_verilator_include();
+        if !up_to_date {
+            self.compile_cxx(name, &compiler);
+            write_file(&self.get_fingerprint_file(), &fingerprint)
+                .unwrap_or_else(|e| panic!("{}", e));
+        }
+        if self.verilator_include_link {
+            self.create_link_to_verilator_include();
+        }
+        if self.rust_bindings {
+            self.create_rust_bindings();
+        }
+        self.write_artifacts(name, &compiler);
+        self.close_log();
+        // cargo directives are only meaningful on the stdout of a build script
+        let build_script = self.cargo_metadata || env::var_os("OUT_DIR").is_some();
+        if self.output == Output::Static && build_script {
+            self.emit_cargo_link(name, &compiler);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a design with one Verilog file in a fresh temp dir, true stands in for
+    // Verilator since nothing is verilated
+    fn design(name: &str) -> (PathBuf, Build) {
+        let dir = env::temp_dir().join(format!("lastlayer_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let top = dir.join("top.v");
+        fs::write(&top, "module top; endmodule\n").unwrap();
+        let mut build = Build::new();
+        build
+            .top_module("top")
+            .out_dir(dir.join("out"))
+            .verilog_file(&top)
+            .verilator_bin("true")
+            .cxx("c++")
+            .profile(Profile::Debug);
+        (dir, build)
+    }
+
+    #[test]
+    fn validate() {
+        let problems = Build::new().validate().unwrap_err().problems;
+        assert_eq!(
+            problems,
+            vec![
+                "top module not set, see Build::top_module",
+                "output dir not set, see Build::out_dir",
+                "no Verilog files, see Build::verilog_file",
+            ]
+        );
+        let (dir, mut build) = design("validate");
+        assert_eq!(build.validate(), Ok(()));
+        build
+            .threads(0)
+            .output(Output::Executable { reset: 1, run: u32::MAX })
+            .verilog_file(dir.join("missing.v"))
+            .cc_include_dir(dir.join("top.v"))
+            .add_register(0, "a", 8)
+            .add_register(0, "b", 8)
+            .add_register(1, "c", 0)
+            .add_probe(0, "u0.1q", 8)
+            .add_probe(1, "a", 8)
+            .add_memory(0, "mem", 0);
+        let problems = build.validate().unwrap_err().problems;
+        assert_eq!(
+            problems,
+            vec![
+                "number of threads must be greater than zero".to_string(),
+                "reset and run cycles must fit in a C int".to_string(),
+                format!("Verilog file {} not found", dir.join("missing.v").display()),
+                format!("C++ include dir {} is not a directory", dir.join("top.v").display()),
+                "register hid 0 already exists".to_string(),
+                "register c has zero width".to_string(),
+                "memory mem has zero width".to_string(),
+                "probe path \"u0.1q\" is not a hierarchical name".to_string(),
+                "probe a and register a generate the same DPI function name".to_string(),
+            ]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    // a verilator script printing version, with an include dir holding verilated.cpp
+    fn fake_verilator(dir: &Path, version: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+        let root = dir.join("verilator");
+        fs::create_dir_all(root.join("include")).unwrap();
+        fs::write(root.join("include").join("verilated.cpp"), "").unwrap();
+        let bin = root.join("verilator");
+        fs::write(&bin, format!("#!/bin/sh\necho \"{}\"\n", version)).unwrap();
+        fs::set_permissions(&bin, fs::Permissions::from_mode(0o755)).unwrap();
+        bin
+    }
+
+    #[test]
+    fn verilator_version() {
+        let (dir, mut build) = design("verilator_version");
+        let check = |build: &mut Build, version: &str| {
+            let bin = fake_verilator(&dir, version);
+            build.verilator_bin(&bin).verilator_root(dir.join("verilator"));
+            build.check_verilator_version()
+        };
+        let old = "Verilator 4.024 2019-12-08 rev v4.024";
+        assert_eq!(check(&mut build, old), Ok(()));
+        assert_eq!(
+            check(&mut build, "Verilator 3.926 2018-08-22 rev verilator_3_926"),
+            Err("Verilator 3.926 2018-08-22 rev verilator_3_926 is too old, at least Verilator 4.024 is required".to_string())
+        );
+        assert_eq!(
+            check(&mut build, "Verilator 5.020 2024-01-01 rev v5.020"),
+            Err("Verilator 5.020 2024-01-01 rev v5.020 is not supported, only Verilator 4.x releases are".to_string())
+        );
+        build.add_force(0, "u.valid", 1);
+        assert_eq!(
+            check(&mut build, old),
+            Err(format!(
+                "{} does not support force and release, forceable signals need Verilator 4.220 or newer",
+                old
+            ))
+        );
+        assert_eq!(check(&mut build, "Verilator 4.220 2022-03-12 rev v4.220"), Ok(()));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn stable_hasher() {
+        let mut hasher = StableHasher::new();
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), "af63dc4c8601ec8c");
+        let a = StableHasher::new().field("ab").field("c").finish();
+        let b = StableHasher::new().field("a").field("bc").finish();
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn fingerprint_invalidation() {
+        let (dir, mut build) = design("fingerprint");
+        build.verilator_root(dir.join("verilator"));
+        let compiler = build.get_compiler();
+        let fingerprint = |build: &Build, name| build.fingerprint(name, &compiler);
+        let base = fingerprint(&build, "top");
+        assert_eq!(base, fingerprint(&build, "top"));
+        fs::write(dir.join("top.v"), "module top; wire w; endmodule\n").unwrap();
+        let source = fingerprint(&build, "top");
+        assert_ne!(base, source);
+        build.define("DEBUG", None);
+        let define = fingerprint(&build, "top");
+        assert_ne!(source, define);
+        build.threads(2);
+        let threads = fingerprint(&build, "top");
+        assert_ne!(define, threads);
+        assert_ne!(threads, fingerprint(&build, "other"));
+        let rtl = dir.join("rtl");
+        let inc = dir.join("inc");
+        fs::create_dir_all(rtl.join("sub")).unwrap();
+        fs::create_dir_all(&inc).unwrap();
+        fs::write(rtl.join("sub").join("defs.vh"), "`define W 8\n").unwrap();
+        fs::write(inc.join("model.h"), "#define W 8\n").unwrap();
+        build.verilog_include_dir(&rtl).cc_include_dir(&inc);
+        let dirs = fingerprint(&build, "top");
+        fs::write(rtl.join("sub").join("defs.vh"), "`define W 16\n").unwrap();
+        let nested = fingerprint(&build, "top");
+        assert_ne!(dirs, nested);
+        fs::write(inc.join("model.h"), "#define W 16\n").unwrap();
+        let header = fingerprint(&build, "top");
+        assert_ne!(nested, header);
+        build.verilator_root(dir.join("other"));
+        assert_ne!(header, fingerprint(&build, "top"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn runtime_objects_shared() {
+        let (dir_a, mut a) = design("cache_a");
+        let (dir_b, mut b) = design("cache_b");
+        let root = dir_a.join("verilator");
+        fs::create_dir_all(root.join("include")).unwrap();
+        let runtime = root.join("include").join("verilated.cpp");
+        fs::write(&runtime, "// runtime\n").unwrap();
+        let cache = dir_a.join("cache");
+        let object = |build: &mut Build| {
+            build.verilator_root(&root).default_include_dirs();
+            let compiler = build.get_compiler();
+            let key = build.get_runtime_key(&compiler, &build.get_runtime_flags(&compiler));
+            build.get_object_file(&cache, &runtime, Some(&key))
+        };
+        let shared = object(&mut a);
+        assert_eq!(shared, object(&mut b));
+        assert_ne!(
+            a.get_cxx_flags(&a.get_compiler()),
+            b.get_cxx_flags(&b.get_compiler())
+        );
+        b.cc_include_dir(&dir_b);
+        assert_eq!(shared, object(&mut b));
+        b.cc_flag("-D_GLIBCXX_USE_CXX11_ABI=0");
+        let abi = object(&mut b);
+        assert_ne!(shared, abi);
+        a.cc_flag("-D_GLIBCXX_USE_CXX11_ABI=0");
+        assert_eq!(abi, object(&mut a));
+        b.threads(2);
+        assert_ne!(abi, object(&mut b));
+        fs::write(&runtime, "// patched runtime\n").unwrap();
+        assert_ne!(shared, object(&mut a));
+        let _ = fs::remove_dir_all(&dir_a);
+        let _ = fs::remove_dir_all(&dir_b);
+    }
+
+    #[test]
+    fn artifacts_sources() {
+        let (dir, mut build) = design("artifacts");
+        build.verilator_root(dir.join("verilator")).default_cc_files();
+        build.create_out_dir();
+        build.write_artifacts("top", &build.get_compiler());
+        let json = fs::read_to_string(dir.join("out").join("artifacts.json")).unwrap();
+        let artifacts: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let sources: Vec<PathBuf> = artifacts["sources"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| PathBuf::from(s.as_str().unwrap()))
+            .collect();
+        let runtime = dir.join("verilator").join("include").join("verilated.cpp");
+        assert!(sources.contains(&runtime));
+        assert!(sources.contains(&dir.join("out").join("lastlayer.cc")));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn cargo_link() {
+        let (dir, mut build) = design("cargo_link");
+        let out_dir = dir.join("out");
+        let search = format!("cargo:rustc-link-search=native={}", out_dir.display());
+        assert_eq!(
+            build.get_cargo_link("top", Some("c++")),
+            vec![
+                search.clone(),
+                "cargo:rustc-link-lib=static=top".to_string(),
+                "cargo:rustc-link-lib=c++".to_string(),
+            ]
+        );
+        build
+            .cc_link_dir("/opt/lib")
+            .cc_link_lib("m")
+            .trace(true)
+            .trace_format(TraceFormat::Fst)
+            .threads(2);
+        assert_eq!(
+            build.get_cargo_link("top", None),
+            vec![
+                search,
+                "cargo:rustc-link-lib=static=top".to_string(),
+                "cargo:rustc-link-search=native=/opt/lib".to_string(),
+                "cargo:rustc-link-lib=m".to_string(),
+                "cargo:rustc-link-lib=z".to_string(),
+                "cargo:rustc-link-lib=pthread".to_string(),
+            ]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn cargo_rerun() {
+        let (dir, mut build) = design("cargo_rerun");
+        let rtl = dir.join("rtl");
+        fs::create_dir_all(rtl.join("sub")).unwrap();
+        fs::write(rtl.join("sub").join("defs.vh"), "`define W 8\n").unwrap();
+        build
+            .verilog_include_dir(&rtl)
+            .handlebars_dir(&rtl)
+            .template("lastlayer.v.hbs", "top.v")
+            .template("custom.hbs", "custom.txt");
+        let rerun = |path: &Path| format!("cargo:rerun-if-changed={}", path.display());
+        let env = |var: &str| format!("cargo:rerun-if-env-changed={}", var);
+        assert_eq!(
+            build.get_cargo_rerun(),
+            vec![
+                rerun(&dir.join("top.v")),
+                rerun(&rtl),
+                rerun(&rtl),
+                rerun(Path::new("custom.hbs")),
+                env("VERILATOR"),
+                env("VERILATOR_ROOT"),
+                env("CXX"),
+                env("AR"),
+                env("CXXSTDLIB"),
+                env("NUM_JOBS"),
+            ]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    // lastlayer.rs.hbs is written by hand, so every constant and function
+    // is checked against lastlayer.h
+    #[test]
+    fn bindings_match_header() {
+        let raw = |t: &str| format!("::std::os::raw::{}", t);
+        let rust_type = |c: &str| match c {
+            "int" => raw("c_int"),
+            "unsigned int*" => format!("*mut {}", raw("c_uint")),
+            "const unsigned int*" => format!("*const {}", raw("c_uint")),
+            "const char*" => format!("*const {}", raw("c_char")),
+            "void*" => format!("*mut {}", raw("c_void")),
+            "unsigned long long" => "u64".to_string(),
+            "void" => "()".to_string(),
+            t => t.to_string(),
+        };
+        let mut header = Vec::new();
+        let code: Vec<&str> = HEADER.lines().filter(|l| !l.starts_with('#')).collect();
+        let code = code.join(" ");
+        let mut rest = code.as_str();
+        while let Some(start) = rest.find("/*") {
+            let end = rest[start..].find("*/").unwrap() + start + 2;
+            header.push(rest[..start].to_string());
+            rest = &rest[end..];
+        }
+        header.push(rest.to_string());
+        let header = header.join(" ");
+        let mut expected = Vec::new();
+        for decl in header.split(';') {
+            let decl = decl.split_whitespace().collect::<Vec<_>>().join(" ");
+            let decl = decl.trim_start_matches("} ");
+            let open = match decl.find('(') {
+                Some(open) if !decl.starts_with("typedef") => open,
+                _ => continue,
+            };
+            let (ret, name) = decl[..open].rsplit_once(' ').unwrap();
+            let args: Vec<String> = decl[open + 1..decl.len() - 1]
+                .split(", ")
+                .filter(|a| !a.is_empty())
+                .map(|a| rust_type(a.rsplit_once(' ').unwrap().0))
+                .collect();
+            expected.push((name.to_string(), rust_type(ret), args));
+        }
+        let bindings = include_str!("handlebars/lastlayer.rs.hbs");
+        let mut found = Vec::new();
+        for line in bindings.lines().map(|l| l.trim()) {
+            if let Some(decl) = line.strip_prefix("pub fn ") {
+                let open = decl.find('(').unwrap();
+                let close = decl.rfind(')').unwrap();
+                let args: Vec<String> = decl[open + 1..close]
+                    .split(", ")
+                    .filter(|a| !a.is_empty())
+                    .map(|a| a.split_once(": ").unwrap().1.to_string())
+                    .collect();
+                let ret = match decl[close..].strip_prefix(") -> ") {
+                    Some(ret) => ret.trim_end_matches(';').to_string(),
+                    None => "()".to_string(),
+                };
+                found.push((decl[..open].to_string(), ret, args));
+            }
+        }
+        assert_eq!(expected, found);
+        for line in HEADER.lines() {
+            if let Some(define) = line.strip_prefix("#define LASTLAYER_") {
+                if let Some((name, value)) = define.split_once(' ') {
+                    let constant = format!(
+                        "pub const LASTLAYER_{}: {} = {};",
+                        name,
+                        raw("c_int"),
+                        value
+                    );
+                    assert!(bindings.contains(&constant), "{} missing", constant);
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,462 @@
+use libloading::{Library, Symbol};
+use std::any::Any;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::io;
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::ptr;
+
+type LastLayerHandle = *mut c_void;
+
+type AllocFn = unsafe extern "C" fn() -> LastLayerHandle;
+type DeallocFn = unsafe extern "C" fn(LastLayerHandle);
+type ReadRegFn = unsafe extern "C" fn(LastLayerHandle, c_int, c_int) -> c_int;
+type WriteRegFn = unsafe extern "C" fn(LastLayerHandle, c_int, c_int, c_int);
+type ReadMemFn = unsafe extern "C" fn(LastLayerHandle, c_int, c_int, c_int) -> c_int;
+type WriteMemFn = unsafe extern "C" fn(LastLayerHandle, c_int, c_int, c_int, c_int);
+type ReadRegWideFn = unsafe extern "C" fn(LastLayerHandle, c_int, *mut c_uint, c_int) -> c_int;
+type WriteRegWideFn = unsafe extern "C" fn(LastLayerHandle, c_int, *const c_uint, c_int) -> c_int;
+type ReadMemWideFn =
+    unsafe extern "C" fn(LastLayerHandle, c_int, c_int, *mut c_uint, c_int) -> c_int;
+type WriteMemWideFn =
+    unsafe extern "C" fn(LastLayerHandle, c_int, c_int, *const c_uint, c_int) -> c_int;
+type ForceFn = unsafe extern "C" fn(LastLayerHandle, c_int, c_int, c_int);
+type ReleaseFn = unsafe extern "C" fn(LastLayerHandle, c_int);
+type CycleFn = unsafe extern "C" fn(LastLayerHandle, c_int) -> c_int;
+type PathFn = unsafe extern "C" fn(LastLayerHandle, *const c_char) -> c_int;
+type TraceFn = unsafe extern "C" fn(LastLayerHandle);
+type TraceDepthFn = unsafe extern "C" fn(LastLayerHandle, c_int);
+type LastErrorFn = unsafe extern "C" fn(LastLayerHandle) -> *const c_char;
+type CounterFn = unsafe extern "C" fn(LastLayerHandle) -> u64;
+type CycleCallback = unsafe extern "C" fn(LastLayerHandle, u64, *mut c_void) -> c_int;
+type SetCycleCallbackFn =
+    unsafe extern "C" fn(LastLayerHandle, Option<CycleCallback>, *mut c_void);
+
+// run status codes defined in lastlayer.h
+const LASTLAYER_COMPLETED: c_int = 0;
+const LASTLAYER_FINISHED: c_int = 1;
+const LASTLAYER_STOPPED: c_int = 2;
+const LASTLAYER_ASSERTION_FAILED: c_int = 3;
+
+// SimError is returned when reset or run ends on something other than the
+// requested number of cycles, each variant holds the location of the cause.
+// See the run status comment in lastlayer.h for when each one is returned
+#[derive(Clone, Debug, PartialEq)]
+pub enum SimError {
+    Finished(String),
+    Stopped(String),
+    AssertionFailed(String),
+    Fatal(String),
+}
+
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SimError::Finished(msg) => write!(f, "simulation finished: {}", msg),
+            SimError::Stopped(msg) => write!(f, "simulation stopped: {}", msg),
+            SimError::AssertionFailed(msg) => write!(f, "assertion failed: {}", msg),
+            SimError::Fatal(msg) => write!(f, "simulation fatal error: {}", msg),
+        }
+    }
+}
+
+impl Error for SimError {}
+
+fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.to_string_lossy().into_owned())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+fn wide_result<T>(res: c_int, value: T) -> io::Result<T> {
+    if res != 0 {
+        return Err(io::Error::other(
+            "wide access needs a declared hid and the DPI module built as SystemVerilog",
+        ));
+    }
+    Ok(value)
+}
+
+// reset and run take the number of cycles as a C int
+fn c_cycles(cycles: u32) -> c_int {
+    match c_int::try_from(cycles) {
+        Ok(n) => n,
+        Err(_) => panic!("{} cycles do not fit in a C int, run them in steps", cycles),
+    }
+}
+
+fn symbol<'a, T>(lib: &'a Library, name: &[u8]) -> Symbol<'a, T> {
+    match unsafe { lib.get(name) } {
+        Ok(s) => s,
+        Err(_) => panic!(
+            "symbol {} not found",
+            String::from_utf8_lossy(&name[..name.len() - 1])
+        ),
+    }
+}
+
+// CycleContext gives the cycle callback access to the device being run,
+// which cannot be borrowed from the callback since run borrows it
+pub struct CycleContext<'a> {
+    lib: &'a Library,
+    handle: LastLayerHandle,
+}
+
+impl CycleContext<'_> {
+    pub fn read_reg(&self, hid: u32, sel: u32) -> u32 {
+        let f: Symbol<ReadRegFn> = symbol(self.lib, b"LastLayerReadReg\0");
+        unsafe { f(self.handle, hid as c_int, sel as c_int) as u32 }
+    }
+
+    pub fn write_reg(&mut self, hid: u32, sel: u32, value: u32) {
+        let f: Symbol<WriteRegFn> = symbol(self.lib, b"LastLayerWriteReg\0");
+        unsafe { f(self.handle, hid as c_int, sel as c_int, value as c_int) }
+    }
+
+    pub fn read_mem(&self, hid: u32, addr: u32, sel: u32) -> u32 {
+        let f: Symbol<ReadMemFn> = symbol(self.lib, b"LastLayerReadMem\0");
+        unsafe { f(self.handle, hid as c_int, addr as c_int, sel as c_int) as u32 }
+    }
+
+    pub fn write_mem(&mut self, hid: u32, addr: u32, sel: u32, value: u32) {
+        let f: Symbol<WriteMemFn> = symbol(self.lib, b"LastLayerWriteMem\0");
+        unsafe {
+            f(
+                self.handle,
+                hid as c_int,
+                addr as c_int,
+                sel as c_int,
+                value as c_int,
+            )
+        }
+    }
+
+    pub fn read_probe(&self, hid: u32, sel: u32) -> u32 {
+        let f: Symbol<ReadRegFn> = symbol(self.lib, b"LastLayerReadProbe\0");
+        unsafe { f(self.handle, hid as c_int, sel as c_int) as u32 }
+    }
+
+    pub fn force(&mut self, hid: u32, sel: u32, value: u32) {
+        let f: Symbol<ForceFn> = symbol(self.lib, b"LastLayerForce\0");
+        unsafe { f(self.handle, hid as c_int, sel as c_int, value as c_int) }
+    }
+
+    pub fn release(&mut self, hid: u32) {
+        let f: Symbol<ReleaseFn> = symbol(self.lib, b"LastLayerRelease\0");
+        unsafe { f(self.handle, hid as c_int) }
+    }
+}
+
+type CycleFunc = dyn FnMut(&mut CycleContext, u64) -> bool;
+
+// lib points to the library of the device while Device::run is running,
+// the device can be moved in between
+struct CallbackState {
+    func: Box<CycleFunc>,
+    lib: *const Library,
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+// a panic must not unwind into C, so it stops the run and is resumed by Device::run
+unsafe extern "C" fn cycle_callback(
+    handle: LastLayerHandle,
+    cycle: u64,
+    user_data: *mut c_void,
+) -> c_int {
+    let state = &mut *(user_data as *mut CallbackState);
+    let mut ctx = CycleContext {
+        lib: &*state.lib,
+        handle,
+    };
+    let func = &mut state.func;
+    match panic::catch_unwind(AssertUnwindSafe(|| func(&mut ctx, cycle))) {
+        Ok(stop) => stop as c_int,
+        Err(p) => {
+            state.panic = Some(p);
+            1
+        }
+    }
+}
+
+// Device loads a library built by lastlayer and owns one simulation handle
+pub struct Device {
+    lib: Library,
+    handle: LastLayerHandle,
+    callback: Option<Box<CallbackState>>,
+}
+
+impl Device {
+    fn symbol<T>(&self, name: &[u8]) -> Symbol<'_, T> {
+        symbol(&self.lib, name)
+    }
+
+    fn context(&self) -> CycleContext<'_> {
+        CycleContext {
+            lib: &self.lib,
+            handle: self.handle,
+        }
+    }
+
+    pub fn new<P: AsRef<Path>>(lib: P) -> io::Result<Device> {
+        let lib = Library::new(lib.as_ref())?;
+        let handle = unsafe {
+            let alloc: Symbol<AllocFn> = lib.get(b"LastLayerAlloc\0")?;
+            alloc()
+        };
+        Ok(Device {
+            lib,
+            handle,
+            callback: None,
+        })
+    }
+
+    pub fn read_reg(&self, hid: u32, sel: u32) -> u32 {
+        self.context().read_reg(hid, sel)
+    }
+
+    pub fn write_reg(&mut self, hid: u32, sel: u32, value: u32) {
+        self.context().write_reg(hid, sel, value)
+    }
+
+    pub fn read_mem(&self, hid: u32, addr: u32, sel: u32) -> u32 {
+        self.context().read_mem(hid, addr, sel)
+    }
+
+    pub fn write_mem(&mut self, hid: u32, addr: u32, sel: u32, value: u32) {
+        self.context().write_mem(hid, addr, sel, value)
+    }
+
+    // wide accesses need the library built with Build::systemverilog, values
+    // are 32-bit words LSB first
+    pub fn read_reg_wide(&self, hid: u32, words: usize) -> io::Result<Vec<u32>> {
+        let mut value = vec![0; words];
+        let f: Symbol<ReadRegWideFn> = self.symbol(b"LastLayerReadRegWide\0");
+        let res = unsafe {
+            f(
+                self.handle,
+                hid as c_int,
+                value.as_mut_ptr(),
+                words as c_int,
+            )
+        };
+        wide_result(res, value)
+    }
+
+    pub fn write_reg_wide(&mut self, hid: u32, value: &[u32]) -> io::Result<()> {
+        let f: Symbol<WriteRegWideFn> = self.symbol(b"LastLayerWriteRegWide\0");
+        let res = unsafe {
+            f(
+                self.handle,
+                hid as c_int,
+                value.as_ptr(),
+                value.len() as c_int,
+            )
+        };
+        wide_result(res, ())
+    }
+
+    pub fn read_mem_wide(&self, hid: u32, addr: u32, words: usize) -> io::Result<Vec<u32>> {
+        let mut value = vec![0; words];
+        let f: Symbol<ReadMemWideFn> = self.symbol(b"LastLayerReadMemWide\0");
+        let res = unsafe {
+            f(
+                self.handle,
+                hid as c_int,
+                addr as c_int,
+                value.as_mut_ptr(),
+                words as c_int,
+            )
+        };
+        wide_result(res, value)
+    }
+
+    pub fn write_mem_wide(&mut self, hid: u32, addr: u32, value: &[u32]) -> io::Result<()> {
+        let f: Symbol<WriteMemWideFn> = self.symbol(b"LastLayerWriteMemWide\0");
+        let res = unsafe {
+            f(
+                self.handle,
+                hid as c_int,
+                addr as c_int,
+                value.as_ptr(),
+                value.len() as c_int,
+            )
+        };
+        wide_result(res, ())
+    }
+
+    pub fn force_wide(&mut self, hid: u32, value: &[u32]) -> io::Result<()> {
+        let f: Symbol<WriteRegWideFn> = self.symbol(b"LastLayerForceWide\0");
+        let res = unsafe {
+            f(
+                self.handle,
+                hid as c_int,
+                value.as_ptr(),
+                value.len() as c_int,
+            )
+        };
+        wide_result(res, ())
+    }
+
+    pub fn read_probe_wide(&self, hid: u32, words: usize) -> io::Result<Vec<u32>> {
+        let mut value = vec![0; words];
+        let f: Symbol<ReadRegWideFn> = self.symbol(b"LastLayerReadProbeWide\0");
+        let res = unsafe {
+            f(
+                self.handle,
+                hid as c_int,
+                value.as_mut_ptr(),
+                words as c_int,
+            )
+        };
+        wide_result(res, value)
+    }
+
+    pub fn read_probe(&self, hid: u32, sel: u32) -> u32 {
+        self.context().read_probe(hid, sel)
+    }
+
+    pub fn force(&mut self, hid: u32, sel: u32, value: u32) {
+        self.context().force(hid, sel, value)
+    }
+
+    pub fn release(&mut self, hid: u32) {
+        self.context().release(hid)
+    }
+
+    // func is called with the cycle number on every rising clock edge
+    // while running, e.g. to model a host-side memory through the context,
+    // returning true stops the run early
+    pub fn set_cycle_callback<F>(&mut self, func: F)
+    where
+        F: FnMut(&mut CycleContext, u64) -> bool + 'static,
+    {
+        let mut state = Box::new(CallbackState {
+            func: Box::new(func),
+            lib: ptr::null(),
+            panic: None,
+        });
+        let user_data = &mut *state as *mut CallbackState as *mut c_void;
+        let f: Symbol<SetCycleCallbackFn> = self.symbol(b"LastLayerSetCycleCallback\0");
+        unsafe { f(self.handle, Some(cycle_callback), user_data) }
+        self.callback = Some(state);
+    }
+
+    pub fn clear_cycle_callback(&mut self) {
+        let f: Symbol<SetCycleCallbackFn> = self.symbol(b"LastLayerSetCycleCallback\0");
+        unsafe { f(self.handle, None, ptr::null_mut()) }
+        self.callback = None;
+    }
+
+    // cycles elapsed since the device was allocated, including reset
+    pub fn cycle(&self) -> u64 {
+        let f: Symbol<CounterFn> = self.symbol(b"LastLayerGetCycle\0");
+        unsafe { f(self.handle) }
+    }
+
+    pub fn time(&self) -> u64 {
+        let f: Symbol<CounterFn> = self.symbol(b"LastLayerGetTime\0");
+        unsafe { f(self.handle) }
+    }
+
+    // tracing needs the library built with Build::trace or Build::vcd_file
+    pub fn trace_open<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let cpath = c_path(path.as_ref())?;
+        let f: Symbol<PathFn> = self.symbol(b"LastLayerTraceOpen\0");
+        if unsafe { f(self.handle, cpath.as_ptr()) } != 0 {
+            return Err(io::Error::other(format!(
+                "failed to open trace file {}",
+                path.as_ref().display()
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn trace_close(&mut self) {
+        let f: Symbol<TraceFn> = self.symbol(b"LastLayerTraceClose\0");
+        unsafe { f(self.handle) }
+    }
+
+    pub fn trace_on(&mut self) {
+        let f: Symbol<TraceFn> = self.symbol(b"LastLayerTraceOn\0");
+        unsafe { f(self.handle) }
+    }
+
+    pub fn trace_off(&mut self) {
+        let f: Symbol<TraceFn> = self.symbol(b"LastLayerTraceOff\0");
+        unsafe { f(self.handle) }
+    }
+
+    // levels takes effect on the next trace_open
+    pub fn trace_depth(&mut self, levels: u32) {
+        let f: Symbol<TraceDepthFn> = self.symbol(b"LastLayerTraceDepth\0");
+        unsafe { f(self.handle, levels as c_int) }
+    }
+
+    // checkpoints need the library built with Build::savable
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let cpath = c_path(path.as_ref())?;
+        let f: Symbol<PathFn> = self.symbol(b"LastLayerSave\0");
+        if unsafe { f(self.handle, cpath.as_ptr()) } != 0 {
+            return Err(io::Error::other(format!(
+                "failed to save checkpoint {}",
+                path.as_ref().display()
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn restore<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let cpath = c_path(path.as_ref())?;
+        let f: Symbol<PathFn> = self.symbol(b"LastLayerRestore\0");
+        if unsafe { f(self.handle, cpath.as_ptr()) } != 0 {
+            return Err(io::Error::other(format!(
+                "failed to restore checkpoint {}",
+                path.as_ref().display()
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn last_error(&self) -> String {
+        let f: Symbol<LastErrorFn> = self.symbol(b"LastLayerLastError\0");
+        unsafe { CStr::from_ptr(f(self.handle)).to_string_lossy().into_owned() }
+    }
+
+    fn check_status(&self, status: c_int) -> Result<(), SimError> {
+        match status {
+            LASTLAYER_COMPLETED => Ok(()),
+            LASTLAYER_FINISHED => Err(SimError::Finished(self.last_error())),
+            LASTLAYER_STOPPED => Err(SimError::Stopped(self.last_error())),
+            LASTLAYER_ASSERTION_FAILED => Err(SimError::AssertionFailed(self.last_error())),
+            _ => Err(SimError::Fatal(self.last_error())),
+        }
+    }
+
+    pub fn reset(&mut self, cycles: u32) -> Result<(), SimError> {
+        let f: Symbol<CycleFn> = self.symbol(b"LastLayerReset\0");
+        let status = unsafe { f(self.handle, c_cycles(cycles)) };
+        self.check_status(status)
+    }
+
+    pub fn run(&mut self, cycles: u32) -> Result<(), SimError> {
+        if let Some(state) = self.callback.as_mut() {
+            state.lib = &self.lib;
+        }
+        let f: Symbol<CycleFn> = self.symbol(b"LastLayerRun\0");
+        let status = unsafe { f(self.handle, c_cycles(cycles)) };
+        if let Some(p) = self.callback.as_mut().and_then(|s| s.panic.take()) {
+            panic::resume_unwind(p);
+        }
+        self.check_status(status)
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        let f: Symbol<DeallocFn> = self.symbol(b"LastLayerDealloc\0");
+        unsafe { f(self.handle) }
+    }
+}
@@ -1,7 +1,47 @@
 use std::env::set_current_dir;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
+// filesystem error with the operation and the paths involved
+#[derive(Debug)]
+pub struct FsError {
+    pub op: &'static str,
+    pub path: PathBuf,
+    pub target: Option<PathBuf>,
+    pub source: io::Error,
+}
+
+impl FsError {
+    pub fn new(op: &'static str, path: &Path, source: io::Error) -> FsError {
+        FsError {
+            op,
+            path: path.to_path_buf(),
+            target: None,
+            source,
+        }
+    }
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed to {} {}", self.op, self.path.display())?;
+        if let Some(t) = &self.target {
+            write!(f, " to {}", t.display())?;
+        }
+        write!(f, ": {}", self.source)
+    }
+}
+
+impl Error for FsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
 pub fn get_lastlayer_root_dir() -> PathBuf {
     PathBuf::from(env!("CARGO_MANIFEST_DIR"))
 }
@@ -16,9 +56,36 @@ pub fn create_date_dir(path: &Path, name: &str) -> PathBuf {
     p
 }
 
-pub fn create_dir(path: &Path) -> std::io::Result<()> {
+// keeps io::Result, the error wraps an FsError so its message has the path
+pub fn create_dir(path: &Path) -> io::Result<()> {
     use std::fs::create_dir_all;
-    create_dir_all(path)?;
+    create_dir_all(path).map_err(|e| io::Error::new(e.kind(), FsError::new("create dir", path, e)))
+}
+
+pub fn write_file(path: &Path, contents: &str) -> Result<(), FsError> {
+    fs::write(path, contents).map_err(|e| FsError::new("write", path, e))
+}
+
+pub fn rename(from: &Path, to: &Path) -> Result<(), FsError> {
+    fs::rename(from, to).map_err(|e| FsError {
+        target: Some(to.to_path_buf()),
+        ..FsError::new("rename", from, e)
+    })
+}
+
+// same as ln -sf, an existing link at dst is replaced
+pub fn symlink_dir(src: &Path, dst: &Path) -> Result<(), FsError> {
+    let err = |e| FsError {
+        target: Some(dst.to_path_buf()),
+        ..FsError::new("link", src, e)
+    };
+    if fs::symlink_metadata(dst).is_ok() {
+        fs::remove_file(dst).map_err(|e| FsError::new("remove", dst, e))?;
+    }
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(src, dst).map_err(err)?;
+    #[cfg(windows)]
+    std::os::windows::fs::symlink_dir(src, dst).map_err(err)?;
     Ok(())
 }
 